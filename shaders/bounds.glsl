@@ -0,0 +1,54 @@
+//World box with a behavior per face, see src/bounds.rs which mirrors this file.
+
+//Values of boundsMinMode and boundsMaxMode.
+const int BOUNDS_REFLECT = 0;
+const int BOUNDS_WRAP = 1;
+const int BOUNDS_KILL = 2;
+const int BOUNDS_OPEN = 3;
+
+uniform vec3 boundsMin;
+uniform vec3 boundsMax;
+//Mode of the faces at boundsMin and boundsMax on each axis, and how much speed reflecting
+//faces keep.
+uniform ivec3 boundsMinMode;
+uniform ivec3 boundsMaxMode;
+uniform vec3 boundsMinDamping;
+uniform vec3 boundsMaxDamping;
+
+//Handles every face the particle is outside of. Returns false when the particle dies.
+bool applyBounds(inout vec3 position, inout vec3 velocity)
+{
+	bool alive = true;
+	for (int axis = 0; axis < 3; axis++)
+	{
+		float size = boundsMax[axis] - boundsMin[axis];
+		if (position[axis] < boundsMin[axis])
+		{
+			int mode = boundsMinMode[axis];
+			if (mode == BOUNDS_REFLECT)
+			{
+				position[axis] = boundsMin[axis] + 0.1;
+				velocity[axis] *= -boundsMinDamping[axis];
+			}
+			else if (mode == BOUNDS_WRAP)
+				position[axis] = boundsMin[axis] + mod(position[axis] - boundsMin[axis], size);
+			else if (mode == BOUNDS_KILL)
+				alive = false;
+		}
+		else if (position[axis] > boundsMax[axis])
+		{
+			int mode = boundsMaxMode[axis];
+			if (mode == BOUNDS_REFLECT)
+			{
+				position[axis] = boundsMax[axis] - 0.1;
+				velocity[axis] *= -boundsMaxDamping[axis];
+			}
+			else if (mode == BOUNDS_WRAP)
+				position[axis] = boundsMin[axis] + mod(position[axis] - boundsMin[axis], size);
+			else if (mode == BOUNDS_KILL)
+				alive = false;
+		}
+	}
+
+	return alive;
+}
@@ -0,0 +1,192 @@
+//Analytic colliders, see src/collider.rs which mirrors this file.
+
+//Values of Collider.shape.
+const int COLLIDER_BOX = 0;
+const int COLLIDER_PLANE = 1;
+const int COLLIDER_FINITE_PLANE = 2;
+const int COLLIDER_CAPSULE = 3;
+const int COLLIDER_CYLINDER = 4;
+const int COLLIDER_TORUS = 5;
+const int COLLIDER_MESH = 6;
+
+//How particles respond to touching a collider or a sphere, see Material in src/collider.rs.
+//stick is the chance that a particle sticks to the surface, flags holds MATERIAL_* bits.
+struct Material
+{
+	float restitution;
+	float friction;
+	float stick;
+	int flags;
+};
+
+//Particles die on contact.
+const int MATERIAL_KILL = 1;
+//The classic response of spheres without a material, handled in the compute shader.
+const int MATERIAL_SPLASH = 2;
+
+//Stuck particles keep touching surfaces they are closer to than this.
+const float STICK_DISTANCE = 0.2;
+
+//How close to a surface a particle has to be to touch it.
+float contactDistance(bool stuck)
+{
+	return stuck ? STICK_DISTANCE : 0.0;
+}
+
+//axisX/Y/Z are the local axes of the shape in world space, size depends on the shape:
+//box half extents, finite plane (half width, thickness, half depth), capsule and
+//cylinder (radius, half length), torus (major radius, minor radius), mesh (samples
+//along x, y and z). A mesh is positioned by its first sample. velocity and angularVelocity
+//(around position) are how the collider moved during the last step.
+struct Collider
+{
+	vec3 position;
+	int shape;
+	vec3 axisX;
+	float restitution;
+	vec3 axisY;
+	float friction;
+	vec3 axisZ;
+	uint gridOffset;
+	vec3 size;
+	float voxelSize;
+	vec3 velocity;
+	float stick;
+	vec3 angularVelocity;
+	int flags;
+};
+
+layout ( binding = 8 ) buffer
+buffer_Colliders
+{
+	Collider Colliders[];
+};
+uniform int colliderCount;
+
+//Baked distances of the mesh colliders, see src/distance_grid.rs. x changes fastest.
+layout ( binding = 9 ) buffer
+buffer_ColliderGrids
+{
+	float ColliderGrids[];
+};
+
+float gridSample(Collider collider, ivec3 i)
+{
+	ivec3 dims = ivec3(collider.size);
+	return ColliderGrids[collider.gridOffset + uint(i.x + dims.x * (i.y + dims.y * i.z))];
+}
+
+//Trilinear inside the grid, outside it the distance to the grid plus the value on its border.
+//q is relative to the first sample.
+float DistanceFieldGrid(Collider collider, vec3 q)
+{
+	//The mesh wasn't loaded.
+	if (collider.size.x < 2.0)
+		return 1.0e9;
+
+	vec3 last = (collider.size - 1.0) * collider.voxelSize;
+	vec3 clamped = clamp(q, vec3(0.0), last);
+	vec3 g = clamped / collider.voxelSize;
+	ivec3 i = min(ivec3(floor(g)), ivec3(collider.size) - 2);
+	vec3 f = g - vec3(i);
+
+	float x00 = mix(gridSample(collider, i), gridSample(collider, i + ivec3(1, 0, 0)), f.x);
+	float x10 = mix(gridSample(collider, i + ivec3(0, 1, 0)), gridSample(collider, i + ivec3(1, 1, 0)), f.x);
+	float x01 = mix(gridSample(collider, i + ivec3(0, 0, 1)), gridSample(collider, i + ivec3(1, 0, 1)), f.x);
+	float x11 = mix(gridSample(collider, i + ivec3(0, 1, 1)), gridSample(collider, i + ivec3(1, 1, 1)), f.x);
+
+	return length(q - clamped) + mix(mix(x00, x10, f.y), mix(x01, x11, f.y), f.z);
+}
+
+float colliderDistance(Collider collider, vec3 p)
+{
+	vec3 d = p - collider.position;
+	vec3 q = vec3(dot(d, collider.axisX), dot(d, collider.axisY), dot(d, collider.axisZ));
+	vec3 size = collider.size;
+
+	switch (collider.shape)
+	{
+		case COLLIDER_BOX:
+			return DistanceFieldBox(q, size);
+		case COLLIDER_PLANE:
+			return DistanceFieldPlane(q);
+		case COLLIDER_FINITE_PLANE:
+			//A slab hanging below the surface.
+			return DistanceFieldBox(vec3(q.x, q.y + size.y * 0.5, q.z), vec3(size.x, size.y * 0.5, size.z));
+		case COLLIDER_CAPSULE:
+			return DistanceFieldCapsule(q, size.x, size.y);
+		case COLLIDER_CYLINDER:
+			return DistanceFieldCylinder(q, size.x, size.y);
+		case COLLIDER_MESH:
+			return DistanceFieldGrid(collider, q);
+		default:
+			return DistanceFieldTorus(q, size.x, size.y);
+	}
+}
+
+//Gradient of the distance field through central differences.
+vec3 colliderNormal(Collider collider, vec3 p)
+{
+	const float e = 0.5;
+	vec3 gradient = vec3(
+		colliderDistance(collider, p + vec3(e, 0.0, 0.0)) - colliderDistance(collider, p - vec3(e, 0.0, 0.0)),
+		colliderDistance(collider, p + vec3(0.0, e, 0.0)) - colliderDistance(collider, p - vec3(0.0, e, 0.0)),
+		colliderDistance(collider, p + vec3(0.0, 0.0, e)) - colliderDistance(collider, p - vec3(0.0, 0.0, e)));
+
+	return length(gradient) > 0.0 ? normalize(gradient) : vec3(0.0, 1.0, 0.0);
+}
+
+//Velocity of the collider's surface at p.
+vec3 surfaceVelocity(Collider collider, vec3 p)
+{
+	return collider.velocity + cross(collider.angularVelocity, p - collider.position);
+}
+
+//Bounces velocity off a surface with the given normal that moves at surface. Returns false
+//when the particle dies.
+bool respond(Material material, vec3 position, vec3 normal, vec3 surface, inout vec3 velocity, inout bool stuck)
+{
+	if ((material.flags & MATERIAL_KILL) != 0)
+		return false;
+	//Stuck particles move with whatever they touch, they only roll for sticking once.
+	if (stuck)
+	{
+		velocity = surface;
+		return true;
+	}
+
+	//Bounce in the frame of the moving surface.
+	vec3 relative = velocity - surface;
+	float vn = dot(relative, normal);
+	if (vn < 0.0)
+	{
+		if (random4(position.xz + position.yy).w < material.stick)
+		{
+			stuck = true;
+			velocity = surface;
+		}
+		else
+		{
+			vec3 tangent = relative - normal * vn;
+			velocity = tangent * (1.0 - material.friction) - normal * (vn * material.restitution) + surface;
+		}
+	}
+
+	return true;
+}
+
+//Pushes a particle that is inside the collider (or, when stuck, within STICK_DISTANCE) out
+//to its surface and responds to the contact with the collider's material. Returns false
+//when the particle dies.
+bool collide(Collider collider, inout vec3 position, inout vec3 velocity, inout bool stuck)
+{
+	float dist = colliderDistance(collider, position);
+	if (dist >= contactDistance(stuck))
+		return true;
+
+	vec3 normal = colliderNormal(collider, position);
+	position += normal * (0.1 - dist);
+
+	Material material = Material(collider.restitution, collider.friction, collider.stick, collider.flags);
+	return respond(material, position, normal, surfaceVelocity(collider, position), velocity, stuck);
+}
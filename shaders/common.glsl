@@ -0,0 +1,60 @@
+//Samples the distance field and returns the value for point p
+//For the moment just use a distance function.
+float DistanceFieldCircle(vec3 p, vec3 sphereOffset, float sphereRadius)
+{
+	return length(sphereOffset - p) - sphereRadius;
+}
+
+//Needs the g_Seed uniform to be declared before the include.
+float rand(vec2 co){
+    return fract(sin(dot(co.xy + g_Seed ,vec2(12.9898,78.233))) * 43758.5453) * 1000.0;
+}
+
+//Four values in [0, 1) for the seed co, see random4() in src/emitter.rs.
+vec4 random4(vec2 co)
+{
+	vec2 p = co + g_Seed;
+	vec4 v = vec4(dot(p, vec2(12.9898, 78.233)), dot(p, vec2(39.3468, 11.1353)),
+		dot(p, vec2(73.156, 52.235)), dot(p, vec2(94.673, 27.374)));
+	return fract(sin(v) * 43758.5453);
+}
+
+//Maps a random value into [0, size), a flat range always yields 0.
+float spawnOffset(float r, float size)
+{
+	return size > 0.0 ? mod(r, size) : 0.0;
+}
+
+//The shapes below are evaluated in their local frame, see shaders/colliders.glsl.
+
+//Box centered on the origin.
+float DistanceFieldBox(vec3 p, vec3 halfExtents)
+{
+	vec3 d = abs(p) - halfExtents;
+	return length(max(d, 0.0)) + min(max(d.x, max(d.y, d.z)), 0.0);
+}
+
+//Everything below y = 0 is inside.
+float DistanceFieldPlane(vec3 p)
+{
+	return p.y;
+}
+
+//Segment along y from -halfLength to halfLength, inflated by radius.
+float DistanceFieldCapsule(vec3 p, float radius, float halfLength)
+{
+	return length(vec3(p.x, p.y - clamp(p.y, -halfLength, halfLength), p.z)) - radius;
+}
+
+//Capped cylinder along y.
+float DistanceFieldCylinder(vec3 p, float radius, float halfHeight)
+{
+	vec2 d = vec2(length(p.xz) - radius, abs(p.y) - halfHeight);
+	return min(max(d.x, d.y), 0.0) + length(max(d, 0.0));
+}
+
+//Ring in the xz plane.
+float DistanceFieldTorus(vec3 p, float majorRadius, float minorRadius)
+{
+	return length(vec2(length(p.xz) - majorRadius, p.y)) - minorRadius;
+}
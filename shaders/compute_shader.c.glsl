@@ -1,7 +1,7 @@
 #version 430
 
-//Size of compute shader local work group - x=32, y=32, z=1(default)
-layout( local_size_x = 32, local_size_y = 32, local_size_z = 1) in;
+//Size of compute shader local work group, LOCAL_SIZE_X/Y are defined by the application.
+layout( local_size_x = LOCAL_SIZE_X, local_size_y = LOCAL_SIZE_Y, local_size_z = 1) in;
 
 layout ( binding = 0 ) buffer
 buffer_InPos
@@ -27,35 +27,120 @@ buffer_OutVelocity
 	vec4	OutVelocity[];
 };
 
+//Recycled particles ranked by index, see spawn_rank.c.glsl. Only filled in when bursts
+//or the spawn budget limit which particles respawn.
+layout ( binding = 13 ) buffer
+buffer_SpawnRanks
+{
+	uint SpawnRanks[];
+};
+
+layout ( binding = 14 ) buffer
+buffer_SpawnGroupOffsets
+{
+	uint SpawnGroupOffsets[];
+};
+
+//Bursts requested with ParticleSystem::emit_burst. end is the number of particles in this
+//and all the previous bursts, velocity* hold the values sampleVelocity() takes.
+struct Burst
+{
+	vec3 position;
+	uint end;
+	vec3 velocityA;
+	int velocityMode;
+	vec3 velocityB;
+	float padding0;
+	vec3 velocityParams;
+	float padding1;
+};
+
+layout ( binding = 6 ) buffer
+buffer_Bursts
+{
+	Burst Bursts[];
+};
+
 // layout( binding = 2, rgba32f) uniform image2D inVelocity;
 // layout( binding = 3, rgba32f) uniform image2D outVelocity;
 
 uniform float dt;
+uniform float gravity;
+
+//How many dead particles may respawn during this step, -1 for all of them.
+uniform int spawnBudget;
 
-const int spheresCount = 20;
-uniform	vec3 sphereOffsets[spheresCount];
-uniform	float sphereRadius[spheresCount];
+//Number of entries in Bursts and the total number of particles they ask for.
+uniform int burstCount;
+uniform int burstParticles;
+
+//Sphere colliders, xyz is the center and w the radius.
+layout ( binding = 7 ) buffer
+buffer_Spheres
+{
+	vec4 Spheres[];
+};
+uniform int sphereCount;
+
+//Velocity of Spheres[i] during the last step in xyz, particles it hits are carried along.
+layout ( binding = 11 ) buffer
+buffer_SphereVelocities
+{
+	vec4 SphereVelocities[];
+};
 
 uniform int g_NumParticles;
 
-const float gAccel = 9.8;
+//Offset added to the respawn hash, derived from the ParticleSystem seed.
+uniform vec2 g_Seed;
+
+#include "common.glsl"
+#include "lifetime.glsl"
+#include "emitter.glsl"
+#include "respawn.glsl"
+#include "colliders.glsl"
+#include "terrain.glsl"
+#include "bounds.glsl"
+
+//Material of Spheres[i], see src/collider.rs.
+layout ( binding = 12 ) buffer
+buffer_SphereMaterials
+{
+	Material SphereMaterials[];
+};
+
+//Number of particles recycled during this step with a lower index than this one.
+uint spawnRank(uint index)
+{
+	if (burstParticles == 0 && spawnBudget < 0)
+		return 0u;
+	return SpawnGroupOffsets[index / uint(RANK_GROUP_SIZE)] + SpawnRanks[index];
+}
 
-//Samples the distance field and returns the value for point p
-//For the moment just use a distance function.
-float DistanceFieldCircle(vec3 p, vec3 sphereOffset, float sphereRadius)
+//Index of the burst a recycled particle joins, -1 once all the burst particles are taken.
+int claimBurst(uint rank)
 {
-	return length(sphereOffset - p) - sphereRadius;
+	if (rank >= uint(burstParticles))
+		return -1;
+
+	int burst = 0;
+	while (burst < burstCount - 1 && rank >= Bursts[burst].end)
+		burst++;
+	return burst;
 }
 
-float rand(vec2 co){
-    return fract(sin(dot(co.xy ,vec2(12.9898,78.233))) * 43758.5453) * 1000.0;
+//Bursts take the lowest ranks, the emitter gets spawnBudget of the ones after them.
+bool trySpawn(uint rank)
+{
+	return spawnBudget < 0 || rank - uint(burstParticles) < uint(spawnBudget);
 }
 
 void main(void)
 {
 	uint index = gl_GlobalInvocationID.x + gl_GlobalInvocationID.y * gl_NumWorkGroups.x * gl_WorkGroupSize.x;
 
-	if(index > g_NumParticles)
+	//The last work group usually sticks out past the end of the buffers.
+	if(index >= uint(g_NumParticles))
 		return;
 
 	vec4 particlePos = InPos[index];
@@ -63,77 +148,84 @@ void main(void)
 
 	//Update Velocity
 	vec4 newParticleVelocity = particleVelocity;
+	float age = particleVelocity.w + dt;
+	bool stuck = isStuck(particlePos);
 	
-	// We could recycle this particle since it's life is over. ( not used now )
-	// For now, recycle the particle if we touched the ground of if our speed is too small.
-	if ( particlePos.y <= 0.001 || ( particlePos.y < 450.0 && length(newParticleVelocity) < 12.2) )
+	if (needsRespawn(index, particlePos, particleVelocity))
 	{
+		uint rank = spawnRank(index);
 		float rand1 = rand(particlePos.xz);
 		float rand2 = rand(particlePos.zx);
-		//Generate a random possition
-		particlePos.x = -700.0 + mod(rand1 * 10, 1400.0);
-		particlePos.y =	 500.0 + mod(rand1, 50);
-		particlePos.z = -700.0 + mod(rand2 * 10, 1400.0);
-		
-		//Generate a random speed.
-		newParticleVelocity.x = -5.0 + mod(rand2 * 100.0f, 10.0);
-		newParticleVelocity.y = -5.0 + mod(rand2 * 100.0f, 10.0);
-		newParticleVelocity.z = -5.0 + mod(rand1 * 100.0f, 10.0);
-		newParticleVelocity.w = 10.0;
+		vec4 u = random4(particlePos.xz);
+		vec4 v = random4(particlePos.zy);
+
+		//Bursts go first, the emitter gets the particles that are left.
+		int burstIndex = claimBurst(rank);
+		if (burstIndex >= 0)
+		{
+			Burst burst = Bursts[burstIndex];
+			particlePos.xyz = burst.position;
+			newParticleVelocity.xyz = sampleVelocity(burst.velocityMode, burst.velocityA, burst.velocityB, burst.velocityParams,
+				burst.position, burst.position, rand1, rand2, v);
+		}
+		else if (trySpawn(rank))
+		{
+			particlePos.xyz = emitPosition(rand1, rand2, u);
+			newParticleVelocity.xyz = emitVelocity(particlePos.xyz, rand1, rand2, v);
+		}
+		else
+		{
+			OutPos[index] = particlePos;
+			OutVelocity[index] = vec4(0.0, 0.0, 0.0, -1.0);
+			return;
+		}
+		newParticleVelocity.w = 0.0;
+		stuck = false;
 	}
 	else
 	{
 		//Just update the particle
 		particlePos.xyz = particlePos.xyz + particleVelocity.xyz*dt;
-		newParticleVelocity.y = newParticleVelocity.y - gAccel * dt;
+		newParticleVelocity.y = newParticleVelocity.y - gravity * dt;
+		newParticleVelocity.w = age;
 	}
 
-	//Collisions
+	//Collisions, a particle that touches a killing material or face dies.
+	bool alive = true;
 	{
-		//Ground Bounce
-		if(particlePos.y <= 0.01)
+		//Ground Bounce on the terrain.
+		if (hasTerrain())
 		{
-			newParticleVelocity.xyz *= 0.2;
-			newParticleVelocity.y *= -1.0;
+			vec3 position = particlePos.xyz;
+			vec3 velocity = newParticleVelocity.xyz;
+			collideWithTerrain(position, velocity);
+			particlePos.xyz = position;
+			newParticleVelocity.xyz = velocity;
 		}
 
-		//Walls
-		const float wallDamping = 0.5;
-		if(particlePos.x > 700) 
-		{
-			particlePos.x = 699.9;
-			newParticleVelocity.x *= -wallDamping;
-		}
-		else if (particlePos.x < -700)
-		{
-			particlePos.x = -699.9;
-			newParticleVelocity.x *= -wallDamping;
-		}
-		else if(particlePos.z > 700)
-		{
-			particlePos.z = 699.9;
-			newParticleVelocity.z *= -wallDamping;
-		}
-		else if(particlePos.z < -700)
+		//Walls, floor and ceiling
 		{
-			particlePos.z = -699.9;
-			newParticleVelocity.z *= -wallDamping;
+			vec3 position = particlePos.xyz;
+			vec3 velocity = newParticleVelocity.xyz;
+			alive = applyBounds(position, velocity);
+			particlePos.xyz = position;
+			newParticleVelocity.xyz = velocity;
 		}
 
 
 		//Try to find the closest sphere to our particle.
 		float minDist = 10000.0;
 		int closestSphereIdx = -1;
-		for(int i = 0; i < spheresCount; i++)
+		for(int i = 0; i < sphereCount; i++)
 		{			
 			//Sphere_t sphere = ;
-			vec3 sphereCenter = sphereOffsets[i];
+			vec3 sphereCenter = Spheres[i].xyz;
 			vec3 localPosition = particlePos.xyz - sphereCenter;
 
 			//Distance field evaluation
-			float dist = DistanceFieldCircle(particlePos.xyz, sphereCenter, sphereRadius[i]);
+			float dist = DistanceFieldCircle(particlePos.xyz, sphereCenter, Spheres[i].w);
 		
-			if(dist < minDist && dist < 0)
+			if(dist < minDist && dist < contactDistance(stuck))
 			{
 				minDist = dist;
 				closestSphereIdx = i;
@@ -146,30 +238,56 @@ void main(void)
 
 			//Compute the reflection vector
 			//Sphere_t closestSphere = spheres[closestSphereIdx];
-			vec3 localPosition = vec3(particlePos.x, particlePos.y, particlePos.z) - sphereOffsets[closestSphereIdx];
+			vec3 localPosition = vec3(particlePos.x, particlePos.y, particlePos.z) - Spheres[closestSphereIdx].xyz;
 			vec3 ReflectionNormal = normalize(localPosition);
-			
-			//Reflect our speed
-			newParticleVelocity.xyz = reflect(newParticleVelocity.xyz,ReflectionNormal);
-			if(abs(newParticleVelocity.x) > 2.0)
-                newParticleVelocity.x *= 0.2;
-            if(abs(newParticleVelocity.y) > 2.0)
-                newParticleVelocity.y *= 0.2;
-            if(abs(newParticleVelocity.z) > 2.0)
-                newParticleVelocity.z *= 0.2;
+			vec3 sphereVelocity = SphereVelocities[closestSphereIdx].xyz;
+			Material sphereMaterial = SphereMaterials[closestSphereIdx];
 
 			//Move the particle away from the collision just a bit.
-			particlePos.xyz = sphereOffsets[closestSphereIdx] + ReflectionNormal * ( sphereRadius[closestSphereIdx] + 0.1);
+			particlePos.xyz = Spheres[closestSphereIdx].xyz + ReflectionNormal * ( Spheres[closestSphereIdx].w + 0.1);
+
+			if ((sphereMaterial.flags & MATERIAL_SPLASH) != 0)
+			{
+				//Reflect our speed, relative to the moving sphere
+				newParticleVelocity.xyz = reflect(newParticleVelocity.xyz - sphereVelocity,ReflectionNormal);
+				if(abs(newParticleVelocity.x) > 2.0)
+					newParticleVelocity.x *= 0.2;
+				if(abs(newParticleVelocity.y) > 2.0)
+					newParticleVelocity.y *= 0.2;
+				if(abs(newParticleVelocity.z) > 2.0)
+					newParticleVelocity.z *= 0.2;
+				newParticleVelocity.xyz += sphereVelocity;
+			}
+			else
+			{
+				vec3 velocity = newParticleVelocity.xyz;
+				alive = alive && respond(sphereMaterial, particlePos.xyz, ReflectionNormal, sphereVelocity, velocity, stuck);
+				newParticleVelocity.xyz = velocity;
+			}
+		}
+
+		for (int i = 0; i < colliderCount && alive; i++)
+		{
+			vec3 position = particlePos.xyz;
+			vec3 velocity = newParticleVelocity.xyz;
+			alive = collide(Colliders[i], position, velocity, stuck);
+			particlePos.xyz = position;
+			newParticleVelocity.xyz = velocity;
 		}
 	}
 
-	//SetColor based on the velocity
-	particlePos.w = length(newParticleVelocity);
+	//Dead particles wait for their turn to respawn, like the ones the emitter has no room for.
+	if (!alive)
+	{
+		newParticleVelocity = vec4(0.0, 0.0, 0.0, -1.0);
+		stuck = false;
+	}
 
-	//Update our remaining life
-	//newParticleVelocity.w -= dt;
+	//SetColor based on the velocity, see isStuck.
+	float speed = length(newParticleVelocity.xyz);
+	particlePos.w = stuck ? -1.0 - speed : speed;
 
-	//Save the new possitions and velocities
-	InPos[index] = particlePos;
-    InVelocity[index] = newParticleVelocity;
+	//Save the new possitions and velocities, InPos/InVelocity stay untouched for the whole step.
+	OutPos[index] = particlePos;
+    OutVelocity[index] = newParticleVelocity;
 }
\ No newline at end of file
@@ -0,0 +1,148 @@
+//Respawn emitter, the GPU half of src/emitter.rs. Needs spawnOffset() from common.glsl.
+
+//Values of emitterShape and velocityMode.
+const int SHAPE_BOX = 0;
+const int SHAPE_POINT = 1;
+const int SHAPE_SPHERE = 2;
+const int SHAPE_SPHERE_SURFACE = 3;
+const int SHAPE_DISC = 4;
+const int SHAPE_CONE = 5;
+const int SHAPE_MESH = 6;
+const int VELOCITY_RANGE = 0;
+const int VELOCITY_RADIAL = 1;
+const int VELOCITY_DIRECTIONAL = 2;
+
+const float PI = 3.14159265;
+
+uniform int emitterShape;
+//Box: min, max. Point/sphere/disc: center. Disc: normal in B. Cone: apex, direction.
+uniform vec3 emitterA;
+uniform vec3 emitterB;
+//Sphere/disc: x = radius. Cone: x = half angle in radians, y = height. Mesh: x = triangle count.
+uniform vec3 emitterParams;
+//Radial velocities point away from here.
+uniform vec3 emitterCenter;
+
+uniform int velocityMode;
+//Range: min, max. Directional: direction in A.
+uniform vec3 velocityA;
+uniform vec3 velocityB;
+//Radial/directional: x = min speed, y = max speed, z = spread in radians.
+uniform vec3 velocityParams;
+
+//a.w is the area of this and all the previous triangles over the total area.
+struct EmitterTriangle
+{
+	vec4 a;
+	vec4 b;
+	vec4 c;
+};
+
+layout ( binding = 5 ) buffer
+buffer_EmitterMesh
+{
+	EmitterTriangle EmitterMesh[];
+};
+
+//Two unit vectors perpendicular to n and to each other.
+void orthonormalBasis(vec3 n, out vec3 t, out vec3 b)
+{
+	vec3 up = abs(n.y) < 0.99 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+	t = normalize(cross(up, n));
+	b = cross(n, t);
+}
+
+//Uniformly distributed direction.
+vec3 unitVector(float u, float v)
+{
+	float z = 2.0 * u - 1.0;
+	float r = sqrt(max(1.0 - z * z, 0.0));
+	float phi = 2.0 * PI * v;
+	return vec3(r * cos(phi), r * sin(phi), z);
+}
+
+vec3 circlePoint(vec3 t, vec3 b, float u)
+{
+	float phi = 2.0 * PI * u;
+	return t * cos(phi) + b * sin(phi);
+}
+
+vec3 sampleMesh(vec4 u)
+{
+	int lo = 0;
+	int hi = int(emitterParams.x) - 1;
+	while (lo < hi)
+	{
+		int mid = (lo + hi) / 2;
+		if (EmitterMesh[mid].a.w < u.x)
+			lo = mid + 1;
+		else
+			hi = mid;
+	}
+
+	EmitterTriangle triangle = EmitterMesh[lo];
+	float s = sqrt(u.y);
+	return triangle.a.xyz * (1.0 - s) + triangle.b.xyz * (s * (1.0 - u.z)) + triangle.c.xyz * (s * u.z);
+}
+
+//rand1/rand2 are the [0, 1000) rand() values the box shape uses, u is uniform in [0, 1).
+vec3 emitPosition(float rand1, float rand2, vec4 u)
+{
+	if (emitterShape == SHAPE_BOX)
+	{
+		vec3 size = emitterB - emitterA;
+		return emitterA + vec3(spawnOffset(rand1 * 10, size.x), spawnOffset(rand1, size.y), spawnOffset(rand2 * 10, size.z));
+	}
+	if (emitterShape == SHAPE_POINT)
+		return emitterA;
+	if (emitterShape == SHAPE_SPHERE)
+		return emitterA + unitVector(u.x, u.y) * emitterParams.x * pow(u.z, 1.0 / 3.0);
+	if (emitterShape == SHAPE_SPHERE_SURFACE)
+		return emitterA + unitVector(u.x, u.y) * emitterParams.x;
+
+	vec3 t, b;
+	if (emitterShape == SHAPE_DISC)
+	{
+		orthonormalBasis(emitterB, t, b);
+		return emitterA + circlePoint(t, b, u.y) * emitterParams.x * sqrt(u.x);
+	}
+	if (emitterShape == SHAPE_CONE)
+	{
+		orthonormalBasis(emitterB, t, b);
+		float h = emitterParams.y * pow(u.x, 1.0 / 3.0);
+		float r = h * tan(emitterParams.x) * sqrt(u.y);
+		return emitterA + emitterB * h + circlePoint(t, b, u.z) * r;
+	}
+
+	return sampleMesh(u);
+}
+
+//Velocity for a particle emitted at position, mode/a/b/params hold the same values as the
+//velocity* uniforms and radial velocities move away from center. v is uniform in [0, 1).
+vec3 sampleVelocity(int mode, vec3 a, vec3 b, vec3 params, vec3 center, vec3 position, float rand1, float rand2, vec4 v)
+{
+	if (mode == VELOCITY_RANGE)
+	{
+		vec3 size = b - a;
+		return a + vec3(spawnOffset(rand2 * 100.0, size.x), spawnOffset(rand2 * 100.0, size.y), spawnOffset(rand1 * 100.0, size.z));
+	}
+
+	float speed = mix(params.x, params.y, v.w);
+	if (mode == VELOCITY_RADIAL)
+	{
+		//Emitted right at the center, any direction will do.
+		vec3 away = position - center;
+		return (length(away) > 0.0001 ? normalize(away) : unitVector(v.x, v.y)) * speed;
+	}
+
+	vec3 t, bitangent;
+	orthonormalBasis(a, t, bitangent);
+	float cosTheta = mix(1.0, cos(params.z), v.x);
+	float sinTheta = sqrt(max(1.0 - cosTheta * cosTheta, 0.0));
+	return (a * cosTheta + circlePoint(t, bitangent, v.y) * sinTheta) * speed;
+}
+
+vec3 emitVelocity(vec3 position, float rand1, float rand2, vec4 v)
+{
+	return sampleVelocity(velocityMode, velocityA, velocityB, velocityParams, emitterCenter, position, rand1, rand2, v);
+}
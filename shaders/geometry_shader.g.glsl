@@ -7,6 +7,7 @@ out vec4 vtxColor;
 in vData
 {
     vec4 transformedColor;
+    float size;
 } v_color[];
 
 
@@ -17,7 +18,10 @@ void main(void)
 
     for ( i = 0; i < gl_in.length(); i++)
     {
-        float size = 2.0;
+        if (v_color[i].size <= 0.0)
+            continue;
+
+        float size = 2.0 * v_color[i].size;
 		gl_Position = gl_in[i].gl_Position + vec4(0.0, 0.0, 0.0, 0.0);
 		vtxColor = v_color[i].transformedColor;
         EmitVertex();
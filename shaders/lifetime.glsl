@@ -0,0 +1,36 @@
+//Per particle lifetime, shared by the compute and the draw shaders.
+//The age of a particle in seconds is stored in the w component of its velocity.
+
+//[min, max] lifetime in seconds, a max of 0 means particles don't age out.
+uniform vec2 lifetimeRange;
+uniform uint lifetimeSeed;
+
+bool hasLifetime()
+{
+	return lifetimeRange.y > 0.0;
+}
+
+//Thomas Wang's integer hash, exact on every GPU unlike the sin() based ones.
+uint wangHash(uint x)
+{
+	x = (x ^ 61u) ^ (x >> 16);
+	x *= 9u;
+	x ^= x >> 4;
+	x *= 0x27d4eb2du;
+	x ^= x >> 15;
+	return x;
+}
+
+//Every particle index gets a fixed lifetime picked from lifetimeRange.
+float particleLifetime(uint index)
+{
+	//The top 24 bits convert to a float without rounding.
+	float r = float(wangHash(index ^ lifetimeSeed) >> 8) / 16777216.0;
+	return lifetimeRange.x + (lifetimeRange.y - lifetimeRange.x) * r;
+}
+
+//0 at birth, 1 at death. Always 0 without a lifetime.
+float normalizedAge(uint index, float age)
+{
+	return hasLifetime() ? clamp(age / particleLifetime(index), 0.0, 1.0) : 0.0;
+}
@@ -0,0 +1,19 @@
+//Which particles get recycled during a step, shared by the compute shader and spawn_rank.c.glsl.
+//Needs lifetime.glsl and the dt uniform.
+
+//Lowest y a particle can be emitted at.
+uniform float emitterFloor;
+
+//Stuck particles (see Material in colliders.glsl) store -1 - speed in position.w instead of the speed.
+bool isStuck(vec4 position)
+{
+	return position.w < 0.0;
+}
+
+//Recycle the particle if its life is over or its speed is too small, stuck particles are
+//slow on purpose. A negative age marks a dead particle that is still waiting for its turn to respawn.
+bool needsRespawn(uint index, vec4 position, vec4 velocity)
+{
+	bool dead = velocity.w < 0.0 || (hasLifetime() && velocity.w + dt >= particleLifetime(index));
+	return dead || (!isStuck(position) && position.y < emitterFloor - 50.0 && length(velocity.xyz) < 7.0);
+}
@@ -0,0 +1,101 @@
+#version 430
+
+//Ranks the particles that get recycled during a step by their index, so the compute shader
+//hands bursts and the spawn budget to the same particles on every run, like update_cpu does.
+//rankPass 0 ranks the particles inside every work group and counts them per group,
+//rankPass 1 runs a single work group that turns the counts into offsets.
+//RANK_GROUP_SIZE is defined by the application.
+layout( local_size_x = RANK_GROUP_SIZE, local_size_y = 1, local_size_z = 1) in;
+
+layout ( binding = 0 ) buffer
+buffer_InPos
+{
+	vec4	InPos[];
+};
+
+layout ( binding = 1 ) buffer
+buffer_InVelocity
+{
+	vec4	InVelocity[];
+};
+
+//Number of recycled particles before this one in its work group.
+layout ( binding = 13 ) buffer
+buffer_SpawnRanks
+{
+	uint SpawnRanks[];
+};
+
+//Recycled particles per work group after pass 0, in all the groups before it after pass 1.
+layout ( binding = 14 ) buffer
+buffer_SpawnGroupOffsets
+{
+	uint SpawnGroupOffsets[];
+};
+
+uniform float dt;
+uniform int g_NumParticles;
+uniform int rankPass;
+//Number of RANK_GROUP_SIZE sized groups covering all the particles.
+uniform int rankGroups;
+
+#include "lifetime.glsl"
+#include "respawn.glsl"
+
+shared uint counts[RANK_GROUP_SIZE];
+
+//Inclusive prefix sum of counts, every invocation has written its own entry before.
+void scanCounts(uint local)
+{
+	for (uint offset = 1u; offset < uint(RANK_GROUP_SIZE); offset *= 2u)
+	{
+		memoryBarrierShared();
+		barrier();
+		uint value = local >= offset ? counts[local - offset] : 0u;
+		memoryBarrierShared();
+		barrier();
+		counts[local] += value;
+	}
+	memoryBarrierShared();
+	barrier();
+}
+
+void main(void)
+{
+	uint local = gl_LocalInvocationID.x;
+
+	if (rankPass == 0)
+	{
+		uint group = gl_WorkGroupID.x + gl_WorkGroupID.y * gl_NumWorkGroups.x;
+		uint index = group * uint(RANK_GROUP_SIZE) + local;
+		bool recycled = index < uint(g_NumParticles) && needsRespawn(index, InPos[index], InVelocity[index]);
+		uint count = recycled ? 1u : 0u;
+
+		counts[local] = count;
+		scanCounts(local);
+
+		if (index < uint(g_NumParticles))
+			SpawnRanks[index] = counts[local] - count;
+		if (local == uint(RANK_GROUP_SIZE) - 1u && group < uint(rankGroups))
+			SpawnGroupOffsets[group] = counts[local];
+	}
+	else
+	{
+		uint carry = 0u;
+		for (int start = 0; start < rankGroups; start += RANK_GROUP_SIZE)
+		{
+			uint group = uint(start) + local;
+			uint count = group < uint(rankGroups) ? SpawnGroupOffsets[group] : 0u;
+
+			counts[local] = count;
+			scanCounts(local);
+
+			if (group < uint(rankGroups))
+				SpawnGroupOffsets[group] = carry + counts[local] - count;
+			carry += counts[RANK_GROUP_SIZE - 1];
+			//Nobody may overwrite counts for the next chunk before everyone read the total.
+			memoryBarrierShared();
+			barrier();
+		}
+	}
+}
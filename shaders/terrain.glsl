@@ -0,0 +1,69 @@
+//Heightmap terrain, see src/terrain.rs which mirrors this file.
+
+//Heights in [0, 1], terrainSize.x per row.
+layout ( binding = 10 ) buffer
+buffer_TerrainHeights
+{
+	float TerrainHeights[];
+};
+
+//Columns and rows of the heightmap, 0 without a terrain.
+uniform ivec2 terrainSize;
+//World x and z of the first and the last pixel.
+uniform vec2 terrainMin;
+uniform vec2 terrainMax;
+//y of black pixels and how much higher white ones are.
+uniform float terrainBase;
+uniform float terrainHeight;
+uniform float terrainRestitution;
+uniform float terrainFriction;
+
+bool hasTerrain()
+{
+	return terrainSize.x > 0;
+}
+
+float terrainSample(ivec2 pixel)
+{
+	return TerrainHeights[pixel.x + pixel.y * terrainSize.x];
+}
+
+//Bilinear between the pixels, the border pixels continue outside the terrain.
+float terrainHeightAt(vec2 xz)
+{
+	vec2 g = clamp((xz - terrainMin) / (terrainMax - terrainMin), 0.0, 1.0) * vec2(terrainSize - 1);
+	ivec2 i = min(ivec2(floor(g)), terrainSize - 2);
+	vec2 f = g - vec2(i);
+
+	float h = mix(mix(terrainSample(i), terrainSample(i + ivec2(1, 0)), f.x),
+		mix(terrainSample(i + ivec2(0, 1)), terrainSample(i + ivec2(1, 1)), f.x), f.y);
+	return terrainBase + h * terrainHeight;
+}
+
+//From the height gradient over one pixel.
+vec3 terrainNormal(vec2 xz)
+{
+	vec2 texel = (terrainMax - terrainMin) / vec2(terrainSize - 1);
+	float dx = terrainHeightAt(xz + vec2(texel.x, 0.0)) - terrainHeightAt(xz - vec2(texel.x, 0.0));
+	float dz = terrainHeightAt(xz + vec2(0.0, texel.y)) - terrainHeightAt(xz - vec2(0.0, texel.y));
+
+	return normalize(vec3(-dx / (2.0 * texel.x), 1.0, -dz / (2.0 * texel.y)));
+}
+
+//Lifts a particle that is below the surface onto it and bounces its velocity off the slope.
+void collideWithTerrain(inout vec3 position, inout vec3 velocity)
+{
+	float ground = terrainHeightAt(position.xz);
+	if (position.y >= ground)
+		return;
+
+	vec3 normal = terrainNormal(position.xz);
+	position.y = ground + 0.1;
+
+	float vn = dot(velocity, normal);
+	if (vn < 0.0)
+	{
+		vec3 tangent = velocity - normal * vn;
+		velocity = tangent * (1.0 - terrainFriction) - normal * (vn * terrainRestitution);
+	}
+}
@@ -0,0 +1,18 @@
+#version 430 core
+
+layout (location = 0) out vec4 FragColor;
+layout (location = 1) out vec4 Highlights;
+
+in vec3 worldNormal;
+
+//Dim so the particles stay the brightest thing in the frame.
+const vec3 ambient = vec3(0.002, 0.0022, 0.0026);
+const vec3 diffuseColor = vec3(0.012, 0.011, 0.01);
+const vec3 lightDirection = vec3(0.27, 0.92, 0.18);
+
+void main()
+{
+    float diffuse = max(dot(normalize(worldNormal), lightDirection), 0.0);
+    FragColor = vec4(ambient + diffuseColor * diffuse, 1.0);
+    Highlights = vec4(0.0, 0.0, 0.0, 1.0);
+}
@@ -0,0 +1,15 @@
+#version 430 core
+
+layout (location = 0) in vec3 position;
+layout (location = 1) in vec3 normal;
+
+uniform mat4 view_from_world;
+uniform mat4 proj_from_view;
+
+out vec3 worldNormal;
+
+void main()
+{
+    worldNormal = normal;
+    gl_Position = proj_from_view * view_from_world * vec4(position, 1.0);
+}
@@ -1,28 +1,80 @@
 #version 430 core
 
+#include "lifetime.glsl"
+
 out vData
 {
     vec4 transformedColor;
+    float size;
 } v_color;
 
-layout (location = 0) in vec4 position;
+layout (location = 0) in vec4 currentPosition;
+layout (location = 1) in vec4 previousPosition;
+//Only w (the age) is used.
+layout (location = 2) in vec4 velocity;
 
 uniform mat4 view_from_world;
 uniform mat4 proj_from_view;
 
+//0 = previous simulation step, 1 = latest one.
+uniform float interpolation;
+
+//Respawned particles jump across the scene, don't draw them halfway there.
+const float maxInterpolationDistance = 50.0;
+
+//Keys spread evenly over the particle's life, MAX_CURVE_POINTS is defined by the application.
+uniform vec4 colorOverLife[MAX_CURVE_POINTS];
+uniform int colorPoints;
+uniform float sizeOverLife[MAX_CURVE_POINTS];
+uniform int sizePoints;
+
+vec4 sampleColor(float t)
+{
+	float x = t * float(colorPoints - 1);
+	int i = int(floor(x));
+	int j = min(i + 1, colorPoints - 1);
+	return mix(colorOverLife[i], colorOverLife[j], x - float(i));
+}
+
+float sampleSize(float t)
+{
+	float x = t * float(sizePoints - 1);
+	int i = int(floor(x));
+	int j = min(i + 1, sizePoints - 1);
+	return mix(sizeOverLife[i], sizeOverLife[j], x - float(i));
+}
+
+//w of a position is the speed, stuck particles store -1 - speed (see respawn.glsl).
+float particleSpeed(vec4 position)
+{
+	return position.w < 0.0 ? -1.0 - position.w : position.w;
+}
+
 void main()
 {
+    vec4 position = currentPosition;
+    float speed = particleSpeed(currentPosition);
+    if (distance(previousPosition.xyz, currentPosition.xyz) < maxInterpolationDistance)
+    {
+        position = mix(previousPosition, currentPosition, interpolation);
+        speed = mix(particleSpeed(previousPosition), speed, interpolation);
+    }
+
     vec4 viewPos = view_from_world * vec4(position.xyz, 1.0);
     gl_Position = proj_from_view * viewPos;
     //v_color.transformedColor = vec4(0.0, 1.0, 1.0, 1.0);
 	
 	//Get the speed and interpolate (mix) between largeSpeed and smallSpeed color.
-	float speed = clamp(position.w, 0.0, 400.0);
+	speed = clamp(speed, 0.0, 400.0);
 	vec3 largeSpeed = vec3(0.4, 0.4, 0.4);
 	vec3 smallSpeed = vec3(1.0, 1.0, 1.0);
 
 	//Interpolate between the two colors
 	vec3 clr = mix(smallSpeed, largeSpeed, vec3(speed/200.0, speed/200.0, speed/200.0));
 	
-	v_color.transformedColor = vec4(clr, (viewPos.z+3000)/3000);
+	float life = normalizedAge(uint(gl_VertexID), velocity.w);
+	vec4 lifeColor = sampleColor(life);
+	v_color.transformedColor = vec4(clr * lifeColor.rgb, lifeColor.a * (viewPos.z+3000)/3000);
+	//Dead particles waiting to respawn have a negative age and aren't drawn.
+	v_color.size = velocity.w < 0.0 ? 0.0 : sampleSize(life);
 }
\ No newline at end of file
@@ -0,0 +1,232 @@
+use graphics::shader::ShaderProgram;
+
+//World box. What happens to particles that leave it is set per face, see BoundsFace. The
+//defaults follow the classic box: particles die on the floor (min_y) and respawn, bounce off
+//the walls on x and z and the top (max_y) is open. Mirrored by shaders/bounds.glsl.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bounds {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+    #[serde(default = "default_wall")]
+    pub min_x: BoundsFace,
+    #[serde(default = "default_wall")]
+    pub max_x: BoundsFace,
+    #[serde(default = "default_floor")]
+    pub min_y: BoundsFace,
+    #[serde(default = "default_ceiling")]
+    pub max_y: BoundsFace,
+    #[serde(default = "default_wall")]
+    pub min_z: BoundsFace,
+    #[serde(default = "default_wall")]
+    pub max_z: BoundsFace
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BoundsFace {
+    //Puts the particle back inside and reverses its speed across the face, keeping damping of it.
+    Reflect {
+        #[serde(default = "default_damping")]
+        damping: f32
+    },
+    //Moves the particle back into the box from the opposite face, as far from it as it was
+    //outside (modulo the size of the box), keeping its velocity.
+    Wrap,
+    //The particle dies and waits for the emitter to respawn it.
+    Kill,
+    //Nothing happens, the particle keeps going.
+    Open
+}
+
+//Values of the boundsMinMode and boundsMaxMode uniforms, see shaders/bounds.glsl.
+const BOUNDS_REFLECT: i32 = 0;
+const BOUNDS_WRAP: i32 = 1;
+const BOUNDS_KILL: i32 = 2;
+const BOUNDS_OPEN: i32 = 3;
+
+//How far reflected particles are put back inside the box.
+const FACE_OFFSET: f32 = 0.1;
+
+
+impl Default for Bounds {
+    fn default() -> Bounds {
+        Bounds {
+            min: [-700.0, 0.0, -700.0],
+            max: [700.0, 10000.0, 700.0],
+            min_x: default_wall(),
+            max_x: default_wall(),
+            min_y: default_floor(),
+            max_y: default_ceiling(),
+            min_z: default_wall(),
+            max_z: default_wall()
+        }
+    }
+}
+
+
+impl Bounds {
+    pub fn validate(&self) -> Result<(), String> {
+        for axis in 0..3 {
+            let (min_face, max_face) = self.faces(axis);
+            for face in [min_face, max_face].iter() {
+                if let BoundsFace::Reflect { damping } = *face {
+                    if !(0.0..=1.0).contains(&damping) {
+                        return Err(format!("bounds damping {} has to be between 0 and 1", damping));
+                    }
+                }
+            }
+
+            let wraps = min_face == BoundsFace::Wrap || max_face == BoundsFace::Wrap;
+            if wraps && self.min[axis] >= self.max[axis] {
+                return Err(format!("bounds min {:?} has to be below max {:?} to wrap", self.min, self.max));
+            }
+        }
+
+        Ok(())
+    }
+
+    //Faces at min[axis] and max[axis].
+    pub fn faces(&self, axis: usize) -> (BoundsFace, BoundsFace) {
+        match axis {
+            0 => (self.min_x, self.max_x),
+            1 => (self.min_y, self.max_y),
+            _ => (self.min_z, self.max_z)
+        }
+    }
+
+    pub fn set_uniforms(&self, program: &ShaderProgram) {
+        let mut modes = [[0i32; 3]; 2];
+        let mut damping = [[0.0f32; 3]; 2];
+        for axis in 0..3 {
+            let (min_face, max_face) = self.faces(axis);
+            for (side, face) in [min_face, max_face].iter().enumerate() {
+                let (mode, face_damping) = face.mode();
+                modes[side][axis] = mode;
+                damping[side][axis] = face_damping;
+            }
+        }
+
+        program.set_uniform_3f("boundsMin", &self.min);
+        program.set_uniform_3f("boundsMax", &self.max);
+        program.set_uniform_3i("boundsMinMode", &modes[0]);
+        program.set_uniform_3i("boundsMaxMode", &modes[1]);
+        program.set_uniform_3f("boundsMinDamping", &damping[0]);
+        program.set_uniform_3f("boundsMaxDamping", &damping[1]);
+    }
+
+    //Same as applyBounds() in shaders/bounds.glsl: handles every face the particle is
+    //outside of. Returns false when the particle dies.
+    pub fn apply(&self, position: &mut [f32; 3], velocity: &mut [f32; 3]) -> bool {
+        let mut alive = true;
+        for axis in 0..3 {
+            let (min_face, max_face) = self.faces(axis);
+            let size = self.max[axis] - self.min[axis];
+            if position[axis] < self.min[axis] {
+                match min_face {
+                    BoundsFace::Reflect { damping } => {
+                        position[axis] = self.min[axis] + FACE_OFFSET;
+                        velocity[axis] *= -damping;
+                    }
+                    BoundsFace::Wrap => position[axis] = self.min[axis] + (position[axis] - self.min[axis]).rem_euclid(size),
+                    BoundsFace::Kill => alive = false,
+                    BoundsFace::Open => ()
+                }
+            } else if position[axis] > self.max[axis] {
+                match max_face {
+                    BoundsFace::Reflect { damping } => {
+                        position[axis] = self.max[axis] - FACE_OFFSET;
+                        velocity[axis] *= -damping;
+                    }
+                    BoundsFace::Wrap => position[axis] = self.min[axis] + (position[axis] - self.min[axis]).rem_euclid(size),
+                    BoundsFace::Kill => alive = false,
+                    BoundsFace::Open => ()
+                }
+            }
+        }
+
+        alive
+    }
+}
+
+
+impl BoundsFace {
+    //BOUNDS_* value and damping for the shader.
+    fn mode(&self) -> (i32, f32) {
+        match *self {
+            BoundsFace::Reflect { damping } => (BOUNDS_REFLECT, damping),
+            BoundsFace::Wrap => (BOUNDS_WRAP, 0.0),
+            BoundsFace::Kill => (BOUNDS_KILL, 0.0),
+            BoundsFace::Open => (BOUNDS_OPEN, 0.0)
+        }
+    }
+}
+
+
+fn default_damping() -> f32 {
+    0.5
+}
+
+
+fn default_wall() -> BoundsFace {
+    BoundsFace::Reflect { damping: default_damping() }
+}
+
+
+fn default_floor() -> BoundsFace {
+    BoundsFace::Kill
+}
+
+
+fn default_ceiling() -> BoundsFace {
+    BoundsFace::Open
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Box from 0 to 10 on every axis, every face with the same mode.
+    fn bounds(face: BoundsFace) -> Bounds {
+        Bounds {
+            min: [0.0, 0.0, 0.0],
+            max: [10.0, 10.0, 10.0],
+            min_x: face,
+            max_x: face,
+            min_y: face,
+            max_y: face,
+            min_z: face,
+            max_z: face
+        }
+    }
+
+    fn apply(bounds: &Bounds, position: [f32; 3]) -> (bool, [f32; 3], [f32; 3]) {
+        let (mut position, mut velocity) = (position, [1.0, -2.0, 3.0]);
+        let alive = bounds.apply(&mut position, &mut velocity);
+        (alive, position, velocity)
+    }
+
+    #[test]
+    fn wrap_moves_particles_to_the_opposite_face() {
+        let wrap = bounds(BoundsFace::Wrap);
+        assert_eq!(apply(&wrap, [12.0, -3.0, 5.0]), (true, [2.0, 7.0, 5.0], [1.0, -2.0, 3.0]));
+        //More than a box size outside still ends up inside.
+        assert_eq!(apply(&wrap, [-25.0, 37.0, 5.0]), (true, [5.0, 7.0, 5.0], [1.0, -2.0, 3.0]));
+        assert_eq!(apply(&wrap, [5.0, 5.0, 100.0]), (true, [5.0, 5.0, 0.0], [1.0, -2.0, 3.0]));
+    }
+
+    #[test]
+    fn kill_faces_kill_the_particles_outside() {
+        let kill = bounds(BoundsFace::Kill);
+        assert_eq!(apply(&kill, [5.0, 5.0, 5.0]), (true, [5.0, 5.0, 5.0], [1.0, -2.0, 3.0]));
+        assert!(!apply(&kill, [5.0, -1.0, 5.0]).0);
+        assert!(!apply(&kill, [11.0, 5.0, 5.0]).0);
+    }
+
+    #[test]
+    fn open_faces_let_particles_through() {
+        let open = bounds(BoundsFace::Open);
+        assert_eq!(apply(&open, [-50.0, 20.0, 5.0]), (true, [-50.0, 20.0, 5.0], [1.0, -2.0, 3.0]));
+    }
+}
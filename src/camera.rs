@@ -8,7 +8,9 @@ pub struct Camera {
     pub proj_from_view: cgmath::Matrix4<f32>,
     pub position: cgmath::Point3<f32>,
     pub angle_yaw: f32,
-    pub angle_pitch: f32
+    pub angle_pitch: f32,
+    //Width / height of the viewport, see set_viewport_size.
+    pub aspect: f32
 }
 
 
@@ -19,7 +21,8 @@ impl Camera {
             proj_from_view: Matrix4::<f32>::identity(),
             position: cgmath::Point3::<f32>::new(0.0, 300.0, -1500.0),
             angle_yaw: 0.0,
-            angle_pitch: 0.0
+            angle_pitch: 0.0,
+            aspect: 16.0 / 9.0
         };
 
         cam.update_matrices();    
@@ -36,6 +39,20 @@ impl Camera {
         
         let rot = super::cgmath::Matrix4::from_angle_y(super::cgmath::Deg::<f32>(self.angle_yaw));
         self.view_from_world = self.view_from_world * rot;
-        self.proj_from_view = perspective(cgmath::Rad(0.785398), 4.0 / 3.0, 0.5, 10000.0);
+        self.proj_from_view = perspective(cgmath::Rad(std::f32::consts::FRAC_PI_4), self.aspect, 0.5, 10000.0);
     }
-}
\ No newline at end of file
+
+    pub fn set_viewport_size(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.aspect = width as f32 / height as f32;
+            self.update_matrices();
+        }
+    }
+}
+
+
+impl Default for Camera {
+    fn default() -> Camera {
+        Camera::new()
+    }
+}
@@ -0,0 +1,833 @@
+use cgmath;
+use cgmath::{Deg, InnerSpace, Matrix, Matrix3, Quaternion, Vector2, Vector3};
+use distance_grid;
+use distance_grid::DistanceGrid;
+use emitter;
+use mesh;
+use std::path::Path;
+use std::sync::Arc;
+
+//Solid shape particles bounce off, evaluated as a signed distance field. Mirrored by
+//shaders/colliders.glsl, keep the two in sync.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Collider {
+    pub shape: ColliderShape,
+    //Share of the velocity along the surface normal that is kept (and reversed) on contact,
+    //0 stops the particle, 1 is a perfect bounce.
+    #[serde(default = "default_restitution")]
+    pub restitution: f32,
+    //Share of the velocity along the surface that is lost on contact.
+    #[serde(default = "default_friction")]
+    pub friction: f32,
+    //See Material.
+    #[serde(default)]
+    pub stick: f32,
+    #[serde(default)]
+    pub kill: bool,
+    //Moves the collider over time, see Animation.
+    #[serde(default)]
+    pub animation: Option<Animation>
+}
+
+//How particles respond to touching a collider or a sphere. restitution and friction are the
+//same as for a Collider.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Material {
+    pub restitution: f32,
+    pub friction: f32,
+    //Chance that a particle hitting the surface sticks to it. A stuck particle takes the velocity
+    //of every surface it touches (or is within STICK_DISTANCE of), so it stays on a static one
+    //and moves with a moving one. It stays stuck until it dies and isn't recycled for being slow.
+    pub stick: f32,
+    //Particles die on contact and wait for the emitter to respawn them.
+    pub kill: bool
+}
+
+//Material as the compute shader expects it (std430), see Material in shaders/colliders.glsl.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MaterialData {
+    restitution: f32,
+    friction: f32,
+    stick: f32,
+    flags: i32
+}
+
+//Rigid motion of a collider, rotated around its pivot (see Collider::pivot) and then moved
+//by translation. rotation is in degrees around x, then y, then z.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColliderTransform {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 3]
+}
+
+//Keyframed ColliderTransform. Keyframes are sorted by time (seconds since the system was
+//created) and linearly interpolated; before the first and after the last one the collider
+//holds still, unless repeat starts the animation over.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Animation {
+    pub keyframes: Vec<Keyframe>,
+    #[serde(default)]
+    pub repeat: bool
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyframe {
+    pub time: f32,
+    #[serde(default)]
+    pub translation: [f32; 3],
+    #[serde(default)]
+    pub rotation: [f32; 3]
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ColliderShape {
+    //rotation is applied around x, then y, then z, in degrees.
+    Box {
+        center: [f32; 3],
+        half_extents: [f32; 3],
+        #[serde(default)]
+        rotation: [f32; 3]
+    },
+    //Everything behind the plane is solid. A plane with a size is a size[0] x size[1]
+    //rectangle (along x and z when the plane is horizontal) that is solid thickness deep.
+    Plane {
+        point: [f32; 3],
+        normal: [f32; 3],
+        #[serde(default)]
+        size: Option<[f32; 2]>,
+        #[serde(default = "default_thickness")]
+        thickness: f32
+    },
+    //Segment from a to b, inflated by radius.
+    Capsule {
+        a: [f32; 3],
+        b: [f32; 3],
+        radius: f32
+    },
+    //Capped cylinder, height is measured along axis.
+    Cylinder {
+        center: [f32; 3],
+        axis: [f32; 3],
+        radius: f32,
+        height: f32
+    },
+    //Ring around axis, major_radius is the distance from the center to the middle of the tube.
+    Torus {
+        center: [f32; 3],
+        axis: [f32; 3],
+        major_radius: f32,
+        minor_radius: f32
+    },
+    //Closed triangle mesh (OBJ or glTF) baked into a DistanceGrid by load_mesh, scaled by
+    //scale and moved to position. resolution is the number of grid samples along the
+    //longest side of the mesh.
+    Mesh {
+        path: String,
+        #[serde(default)]
+        position: [f32; 3],
+        #[serde(default = "default_scale")]
+        scale: f32,
+        #[serde(default = "default_resolution")]
+        resolution: u32,
+        #[serde(skip)]
+        grid: Option<Arc<DistanceGrid>>
+    }
+}
+
+//Collider as the Colliders buffer in the compute shader expects it (std430). The shape is
+//evaluated in its local frame: axis_x/y/z are the local axes in world space.
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ColliderSdf {
+    position: [f32; 3],
+    shape: i32,
+    axis_x: [f32; 3],
+    restitution: f32,
+    axis_y: [f32; 3],
+    friction: f32,
+    axis_z: [f32; 3],
+    //Index of the first sample of a mesh collider in the ColliderGrids buffer.
+    grid_offset: u32,
+    //Per shape, see DistanceField* in shaders/common.glsl. Samples along x, y and z for meshes.
+    size: [f32; 3],
+    voxel_size: f32,
+    //Motion of the collider during the last step, see set_motion.
+    velocity: [f32; 3],
+    stick: f32,
+    angular_velocity: [f32; 3],
+    //MATERIAL_* bits.
+    flags: i32
+}
+
+//Values of ColliderSdf::shape, see shaders/colliders.glsl.
+const COLLIDER_BOX: i32 = 0;
+const COLLIDER_PLANE: i32 = 1;
+const COLLIDER_FINITE_PLANE: i32 = 2;
+const COLLIDER_CAPSULE: i32 = 3;
+const COLLIDER_CYLINDER: i32 = 4;
+const COLLIDER_TORUS: i32 = 5;
+const COLLIDER_MESH: i32 = 6;
+
+//Bits of MaterialData::flags. MATERIAL_SPLASH is the classic response of spheres without a
+//material: reflect, then slow down every fast component of the velocity.
+const MATERIAL_KILL: i32 = 1;
+const MATERIAL_SPLASH: i32 = 2;
+
+//How far particles are pushed out of a collider, same as for spheres.
+const SURFACE_OFFSET: f32 = 0.1;
+
+//Step used for the central differences in normal().
+const NORMAL_EPSILON: f32 = 0.5;
+
+//Stuck particles keep touching surfaces they are closer to than this, twice the
+//SURFACE_OFFSET they are pushed out to.
+pub const STICK_DISTANCE: f32 = 0.2;
+
+//Distance to a mesh collider whose mesh wasn't loaded.
+const UNLOADED_DISTANCE: f32 = 1.0e9;
+
+//Upper limit for ColliderShape::Mesh::resolution, a padded grid of this size is about 70MB.
+const MAX_RESOLUTION: u32 = 256;
+
+
+impl Collider {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.shape {
+            ColliderShape::Box { half_extents, .. } => {
+                if half_extents.iter().any(|&extent| extent <= 0.0) {
+                    return Err(format!("box half_extents {:?} have to be positive", half_extents));
+                }
+            }
+            ColliderShape::Plane { normal, size, thickness, .. } => {
+                check_direction("plane normal", normal)?;
+                if let Some(size) = size {
+                    if size[0] <= 0.0 || size[1] <= 0.0 {
+                        return Err(format!("plane size {:?} has to be positive", size));
+                    }
+                    check_positive("plane thickness", thickness)?;
+                }
+            }
+            ColliderShape::Capsule { radius, .. } => check_positive("capsule radius", radius)?,
+            ColliderShape::Cylinder { axis, radius, height, .. } => {
+                check_direction("cylinder axis", axis)?;
+                check_positive("cylinder radius", radius)?;
+                check_positive("cylinder height", height)?;
+            }
+            ColliderShape::Torus { axis, major_radius, minor_radius, .. } => {
+                check_direction("torus axis", axis)?;
+                check_positive("torus minor_radius", minor_radius)?;
+                if major_radius < minor_radius {
+                    return Err(format!("torus major_radius {} is below minor_radius {}", major_radius, minor_radius));
+                }
+            }
+            ColliderShape::Mesh { scale, resolution, .. } => {
+                check_positive("mesh scale", scale)?;
+                if !(4..=MAX_RESOLUTION).contains(&resolution) {
+                    return Err(format!("mesh resolution {} has to be between 4 and {}", resolution, MAX_RESOLUTION));
+                }
+            }
+        }
+
+        self.material().validate().map_err(|err| format!("collider {}", err))?;
+        if let Some(ref animation) = self.animation {
+            animation.validate()?;
+        }
+
+        Ok(())
+    }
+
+    //Reads and bakes the mesh of a Mesh shape, path is relative to base_dir. Other shapes are left alone.
+    pub fn load_mesh(&mut self, base_dir: &Path) -> Result<(), String> {
+        if let ColliderShape::Mesh { ref path, position, scale, resolution, ref mut grid } = self.shape {
+            let full_path = base_dir.join(path);
+            let mut triangles = mesh::load_triangles(&full_path)
+                .map_err(|err| format!("can't load collider mesh {}: {}", full_path.display(), err))?;
+            if triangles.is_empty() {
+                return Err(format!("collider mesh {} has no triangles", full_path.display()));
+            }
+            mesh::place(&mut triangles, position, scale);
+
+            *grid = Some(Arc::new(DistanceGrid::bake(&triangles, resolution)));
+        }
+
+        Ok(())
+    }
+
+    pub fn material(&self) -> Material {
+        Material {
+            restitution: self.restitution,
+            friction: self.friction,
+            stick: self.stick,
+            kill: self.kill
+        }
+    }
+
+    pub fn set_material(&mut self, material: Material) {
+        self.restitution = material.restitution;
+        self.friction = material.friction;
+        self.stick = material.stick;
+        self.kill = material.kill;
+    }
+
+    //Point the collider rotates around: the center of the shape, the point of a plane, the
+    //middle of a capsule and the position of a mesh.
+    pub fn pivot(&self) -> [f32; 3] {
+        match self.shape {
+            ColliderShape::Box { center, .. } | ColliderShape::Cylinder { center, .. } | ColliderShape::Torus { center, .. } => center,
+            ColliderShape::Plane { point, .. } => point,
+            ColliderShape::Capsule { a, b, .. } => ((vec3(a) + vec3(b)) * 0.5).into(),
+            ColliderShape::Mesh { position, .. } => position
+        }
+    }
+
+    //The samples of a mesh collider are appended to grids. A mesh that wasn't loaded yet
+    //never collides.
+    pub fn sdf(&self, grids: &mut Vec<f32>) -> ColliderSdf {
+        let mut grid_offset = 0;
+        let mut voxel_size = 0.0;
+        let (shape, position, frame, size) = match self.shape {
+            ColliderShape::Box { center, half_extents, rotation } => {
+                (COLLIDER_BOX, vec3(center), rotation_matrix(rotation), vec3(half_extents))
+            }
+            ColliderShape::Plane { point, normal, size: None, .. } =>
+                (COLLIDER_PLANE, vec3(point), frame_from_up(vec3(normal)), Vector3::new(0.0, 0.0, 0.0)),
+            ColliderShape::Plane { point, normal, size: Some(size), thickness } =>
+                (COLLIDER_FINITE_PLANE, vec3(point), frame_from_up(vec3(normal)), Vector3::new(size[0] * 0.5, thickness, size[1] * 0.5)),
+            ColliderShape::Capsule { a, b, radius } => {
+                let (a, b) = (vec3(a), vec3(b));
+                let axis = b - a;
+                let up = if axis.magnitude() > 0.0 { axis } else { Vector3::unit_y() };
+                (COLLIDER_CAPSULE, (a + b) * 0.5, frame_from_up(up), Vector3::new(radius, axis.magnitude() * 0.5, 0.0))
+            }
+            ColliderShape::Cylinder { center, axis, radius, height } =>
+                (COLLIDER_CYLINDER, vec3(center), frame_from_up(vec3(axis)), Vector3::new(radius, height * 0.5, 0.0)),
+            ColliderShape::Torus { center, axis, major_radius, minor_radius } =>
+                (COLLIDER_TORUS, vec3(center), frame_from_up(vec3(axis)), Vector3::new(major_radius, minor_radius, 0.0)),
+            ColliderShape::Mesh { ref grid, .. } => {
+                let identity = Matrix3::from_cols(Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z());
+                match *grid {
+                    Some(ref grid) => {
+                        grid_offset = grids.len() as u32;
+                        voxel_size = grid.voxel_size;
+                        grids.extend_from_slice(&grid.values);
+                        let dims = Vector3::new(grid.dims[0] as f32, grid.dims[1] as f32, grid.dims[2] as f32);
+                        (COLLIDER_MESH, vec3(grid.origin), identity, dims)
+                    }
+                    None => (COLLIDER_MESH, Vector3::new(0.0, 0.0, 0.0), identity, Vector3::new(0.0, 0.0, 0.0))
+                }
+            }
+        };
+
+        let mut sdf = ColliderSdf {
+            position: position.into(),
+            shape,
+            axis_x: frame.x.into(),
+            axis_y: frame.y.into(),
+            axis_z: frame.z.into(),
+            grid_offset,
+            size: size.into(),
+            voxel_size,
+            ..ColliderSdf::default()
+        };
+        sdf.set_material(&self.material());
+
+        sdf
+    }
+}
+
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            restitution: default_restitution(),
+            friction: default_friction(),
+            stick: 0.0,
+            kill: false
+        }
+    }
+}
+
+
+impl Material {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(0.0..=1.0).contains(&self.restitution) || !(0.0..=1.0).contains(&self.friction) {
+            return Err(format!("restitution {} and friction {} have to be between 0 and 1",
+                self.restitution, self.friction));
+        }
+        if !(0.0..=1.0).contains(&self.stick) {
+            return Err(format!("stick {} has to be between 0 and 1", self.stick));
+        }
+
+        Ok(())
+    }
+
+    pub fn data(&self) -> MaterialData {
+        MaterialData {
+            restitution: self.restitution,
+            friction: self.friction,
+            stick: self.stick,
+            flags: if self.kill { MATERIAL_KILL } else { 0 }
+        }
+    }
+
+    //Same as respond() in shaders/colliders.glsl: bounces velocity off a surface with the given
+    //normal that moves at surface_velocity. Returns false when the particle dies. A particle
+    //that isn't stuck yet sticks with a chance of stick; seed is the ParticleSystem's seed
+    //offset, it decides together with position whether it does.
+    pub fn respond(&self, position: [f32; 3], normal: [f32; 3], surface_velocity: [f32; 3], velocity: &mut [f32; 3], stuck: &mut bool, seed: [f32; 2]) -> bool {
+        if self.kill {
+            return false;
+        }
+        if *stuck {
+            *velocity = surface_velocity;
+            return true;
+        }
+
+        //Bounce in the frame of the moving surface.
+        let (normal, surface_velocity) = (vec3(normal), vec3(surface_velocity));
+        let v = vec3(*velocity) - surface_velocity;
+        let vn = v.dot(normal);
+        if vn < 0.0 {
+            let roll = emitter::random4(position[0] + position[1] + seed[0], position[2] + position[1] + seed[1])[3];
+            if roll < self.stick {
+                *stuck = true;
+                *velocity = surface_velocity.into();
+            } else {
+                let tangent = v - normal * vn;
+                *velocity = (tangent * (1.0 - self.friction) - normal * (vn * self.restitution) + surface_velocity).into();
+            }
+        }
+
+        true
+    }
+}
+
+
+impl MaterialData {
+    //The response of spheres that don't have a Material, see MATERIAL_SPLASH.
+    pub fn splash() -> MaterialData {
+        MaterialData {
+            flags: MATERIAL_SPLASH,
+            ..MaterialData::default()
+        }
+    }
+}
+
+
+impl Animation {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.keyframes.is_empty() {
+            return Err("collider animation needs at least one keyframe".to_string());
+        }
+        if self.keyframes[0].time < 0.0 || self.keyframes.windows(2).any(|pair| pair[0].time >= pair[1].time) {
+            return Err("collider keyframe times have to start at 0 or later and increase".to_string());
+        }
+
+        Ok(())
+    }
+
+    pub fn transform_at(&self, time: f32) -> ColliderTransform {
+        let (first, last) = (self.keyframes[0], self.keyframes[self.keyframes.len() - 1]);
+        let mut time = time;
+        if self.repeat && last.time > first.time && time > last.time {
+            time = first.time + (time - first.time) % (last.time - first.time);
+        }
+
+        let next = self.keyframes.iter().position(|keyframe| keyframe.time > time);
+        let (from, to) = match next {
+            None => (last, last),
+            Some(0) => (first, first),
+            Some(index) => (self.keyframes[index - 1], self.keyframes[index])
+        };
+
+        let t = if to.time > from.time { (time - from.time) / (to.time - from.time) } else { 0.0 };
+        let lerp = |a: [f32; 3], b: [f32; 3]| [a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t];
+        ColliderTransform {
+            translation: lerp(from.translation, to.translation),
+            rotation: lerp(from.rotation, to.rotation)
+        }
+    }
+}
+
+
+impl ColliderSdf {
+    pub fn material(&self) -> Material {
+        Material {
+            restitution: self.restitution,
+            friction: self.friction,
+            stick: self.stick,
+            kill: self.flags & MATERIAL_KILL != 0
+        }
+    }
+
+    pub fn set_material(&mut self, material: &Material) {
+        let data = material.data();
+        self.restitution = data.restitution;
+        self.friction = data.friction;
+        self.stick = data.stick;
+        self.flags = data.flags;
+    }
+
+    //The collider moved by transform around pivot, see ColliderTransform.
+    pub fn transformed(&self, pivot: [f32; 3], transform: &ColliderTransform) -> ColliderSdf {
+        let rotation = rotation_matrix(transform.rotation);
+        let pivot = vec3(pivot);
+        let frame = rotation * Matrix3::from_cols(vec3(self.axis_x), vec3(self.axis_y), vec3(self.axis_z));
+
+        ColliderSdf {
+            position: (pivot + rotation * (vec3(self.position) - pivot) + vec3(transform.translation)).into(),
+            axis_x: frame.x.into(),
+            axis_y: frame.y.into(),
+            axis_z: frame.z.into(),
+            ..*self
+        }
+    }
+
+    //Sets the velocity the surface had while it moved from previous to here in dt seconds,
+    //particles that touch it are carried along.
+    pub fn set_motion(&mut self, previous: &ColliderSdf, dt: f32) {
+        if dt <= 0.0 {
+            return;
+        }
+
+        let velocity = (vec3(self.position) - vec3(previous.position)) / dt;
+        let frame = Matrix3::from_cols(vec3(self.axis_x), vec3(self.axis_y), vec3(self.axis_z));
+        let previous_frame = Matrix3::from_cols(vec3(previous.axis_x), vec3(previous.axis_y), vec3(previous.axis_z));
+        let mut delta = Quaternion::from(frame * previous_frame.transpose());
+        if delta.s < 0.0 {
+            delta = -delta;
+        }
+
+        let sin = delta.v.magnitude();
+        let angular_velocity = if sin > 1e-6 {
+            delta.v * (2.0 * sin.atan2(delta.s) / (sin * dt))
+        } else {
+            Vector3::new(0.0, 0.0, 0.0)
+        };
+
+        self.velocity = velocity.into();
+        self.angular_velocity = angular_velocity.into();
+    }
+
+    //Velocity of the surface at p, same as surfaceVelocity() in shaders/colliders.glsl.
+    pub fn surface_velocity(&self, p: [f32; 3]) -> [f32; 3] {
+        (vec3(self.velocity) + vec3(self.angular_velocity).cross(vec3(p) - vec3(self.position))).into()
+    }
+
+    //Same as colliderDistance() in shaders/colliders.glsl. grids is what sdf() filled.
+    pub fn distance(&self, p: [f32; 3], grids: &[f32]) -> f32 {
+        let d = vec3(p) - vec3(self.position);
+        let q = Vector3::new(d.dot(vec3(self.axis_x)), d.dot(vec3(self.axis_y)), d.dot(vec3(self.axis_z)));
+        let size = vec3(self.size);
+
+        match self.shape {
+            COLLIDER_BOX => distance_field_box(q, size),
+            COLLIDER_PLANE => q.y,
+            COLLIDER_FINITE_PLANE => distance_field_box(
+                Vector3::new(q.x, q.y + size.y * 0.5, q.z),
+                Vector3::new(size.x, size.y * 0.5, size.z)),
+            COLLIDER_CAPSULE => Vector3::new(q.x, q.y - q.y.max(-size.y).min(size.y), q.z).magnitude() - size.x,
+            COLLIDER_CYLINDER => {
+                let d = Vector2::new(Vector2::new(q.x, q.z).magnitude() - size.x, q.y.abs() - size.y);
+                d.x.max(d.y).min(0.0) + Vector2::new(d.x.max(0.0), d.y.max(0.0)).magnitude()
+            }
+            COLLIDER_MESH => {
+                if size.x < 2.0 {
+                    return UNLOADED_DISTANCE;
+                }
+                let dims = [size.x as u32, size.y as u32, size.z as u32];
+                distance_grid::sample(&grids[self.grid_offset as usize..], [0.0; 3], self.voxel_size, dims, q.into())
+            }
+            _ => Vector2::new(Vector2::new(q.x, q.z).magnitude() - size.x, q.y).magnitude() - size.y
+        }
+    }
+
+    //Same as colliderNormal() in shaders/colliders.glsl.
+    pub fn normal(&self, p: [f32; 3], grids: &[f32]) -> [f32; 3] {
+        let e = NORMAL_EPSILON;
+        let gradient = Vector3::new(
+            self.distance([p[0] + e, p[1], p[2]], grids) - self.distance([p[0] - e, p[1], p[2]], grids),
+            self.distance([p[0], p[1] + e, p[2]], grids) - self.distance([p[0], p[1] - e, p[2]], grids),
+            self.distance([p[0], p[1], p[2] + e], grids) - self.distance([p[0], p[1], p[2] - e], grids));
+
+        if gradient.magnitude() > 0.0 { gradient.normalize().into() } else { [0.0, 1.0, 0.0] }
+    }
+
+    //Same as collide() in shaders/colliders.glsl: pushes a particle that is inside (or, when
+    //stuck, within STICK_DISTANCE) out to the surface and responds to the contact with the
+    //collider's material. Returns false when the particle dies, see Material::respond.
+    pub fn collide(&self, position: &mut [f32; 3], velocity: &mut [f32; 3], stuck: &mut bool, grids: &[f32], seed: [f32; 2]) -> bool {
+        let dist = self.distance(*position, grids);
+        if dist >= contact_distance(*stuck) {
+            return true;
+        }
+
+        let normal = vec3(self.normal(*position, grids));
+        *position = (vec3(*position) + normal * (SURFACE_OFFSET - dist)).into();
+
+        self.material().respond(*position, normal.into(), self.surface_velocity(*position), velocity, stuck, seed)
+    }
+}
+
+
+//Same as contactDistance() in shaders/colliders.glsl: how close to a surface a particle has
+//to be to touch it.
+pub fn contact_distance(stuck: bool) -> f32 {
+    if stuck { STICK_DISTANCE } else { 0.0 }
+}
+
+
+fn default_restitution() -> f32 {
+    0.5
+}
+
+
+fn default_friction() -> f32 {
+    0.1
+}
+
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+
+fn default_resolution() -> u32 {
+    48
+}
+
+
+fn default_thickness() -> f32 {
+    10.0
+}
+
+
+fn check_positive(name: &str, value: f32) -> Result<(), String> {
+    if value > 0.0 {
+        Ok(())
+    } else {
+        Err(format!("{} has to be positive, got {}", name, value))
+    }
+}
+
+
+fn check_direction(name: &str, direction: [f32; 3]) -> Result<(), String> {
+    if vec3(direction).magnitude() > 0.0 {
+        Ok(())
+    } else {
+        Err(format!("{} can't be a zero vector", name))
+    }
+}
+
+
+//Same as DistanceFieldBox() in shaders/common.glsl.
+fn distance_field_box(q: Vector3<f32>, half_extents: Vector3<f32>) -> f32 {
+    let d = Vector3::new(q.x.abs() - half_extents.x, q.y.abs() - half_extents.y, q.z.abs() - half_extents.z);
+    let outside = Vector3::new(d.x.max(0.0), d.y.max(0.0), d.z.max(0.0)).magnitude();
+    outside + d.x.max(d.y.max(d.z)).min(0.0)
+}
+
+
+//Rotation around x, then y, then z, in degrees.
+fn rotation_matrix(degrees: [f32; 3]) -> Matrix3<f32> {
+    Matrix3::from_angle_z(Deg(degrees[2])) * Matrix3::from_angle_y(Deg(degrees[1])) * Matrix3::from_angle_x(Deg(degrees[0]))
+}
+
+
+//Frame whose local y axis is up. Local x stays on the world x axis for a horizontal up.
+fn frame_from_up(up: Vector3<f32>) -> Matrix3<f32> {
+    let y = up.normalize();
+    let reference = if y.z.abs() < 0.99 { Vector3::unit_z() } else { Vector3::unit_x() };
+    let x = y.cross(reference).normalize();
+    let z = x.cross(y);
+
+    Matrix3::from_cols(x, y, z)
+}
+
+
+fn vec3(v: [f32; 3]) -> Vector3<f32> {
+    cgmath::Vector3::from(v)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collider(shape: ColliderShape, restitution: f32, friction: f32) -> Collider {
+        Collider { shape, restitution, friction, stick: 0.0, kill: false, animation: None }
+    }
+
+    fn sdf(shape: ColliderShape) -> ColliderSdf {
+        collider(shape, default_restitution(), default_friction()).sdf(&mut Vec::new())
+    }
+
+    fn assert_distances(sdf: &ColliderSdf, expected: &[([f32; 3], f32)]) {
+        for &(p, distance) in expected {
+            let actual = sdf.distance(p, &[]);
+            assert!((actual - distance).abs() < 1e-4, "distance at {:?} is {}, expected {}", p, actual, distance);
+        }
+    }
+
+    fn assert_vec3(actual: [f32; 3], expected: [f32; 3]) {
+        assert!(actual.iter().zip(expected.iter()).all(|(a, b)| (a - b).abs() < 1e-4), "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn box_distance() {
+        let cuboid = sdf(ColliderShape::Box { center: [0.0, 0.0, 0.0], half_extents: [1.0, 2.0, 3.0], rotation: [0.0; 3] });
+        assert_distances(&cuboid, &[([0.0, 0.0, 0.0], -1.0), ([3.0, 0.0, 0.0], 2.0), ([0.0, 2.0, 0.0], 0.0), ([2.0, 3.0, 0.0], 2f32.sqrt())]);
+
+        //Turned a quarter around z, the long side now lies along x.
+        let turned = sdf(ColliderShape::Box { center: [0.0, 0.0, 0.0], half_extents: [1.0, 2.0, 3.0], rotation: [0.0, 0.0, 90.0] });
+        assert_distances(&turned, &[([1.5, 0.0, 0.0], -0.5), ([0.0, 1.5, 0.0], 0.5)]);
+    }
+
+    #[test]
+    fn plane_distance() {
+        let ground = sdf(ColliderShape::Plane { point: [0.0, 10.0, 0.0], normal: [0.0, 1.0, 0.0], size: None, thickness: 10.0 });
+        assert_distances(&ground, &[([5.0, 15.0, 5.0], 5.0), ([1000.0, 8.0, -1000.0], -2.0)]);
+
+        //The normal doesn't have to be normalized.
+        let wall = sdf(ColliderShape::Plane { point: [0.0, 0.0, 0.0], normal: [0.0, 0.0, 2.0], size: None, thickness: 10.0 });
+        assert_distances(&wall, &[([0.0, 0.0, 3.0], 3.0), ([7.0, 7.0, -1.0], -1.0)]);
+    }
+
+    #[test]
+    fn finite_plane_distance() {
+        //Solid from y = -10 to 0, x in [-5, 5] and z in [-10, 10].
+        let slab = sdf(ColliderShape::Plane { point: [0.0, 0.0, 0.0], normal: [0.0, 1.0, 0.0], size: Some([10.0, 20.0]), thickness: 10.0 });
+        assert_distances(&slab, &[
+            ([0.0, -5.0, 0.0], -5.0),
+            ([0.0, -1.0, 0.0], -1.0),
+            ([0.0, 3.0, 0.0], 3.0),
+            ([8.0, 0.0, 0.0], 3.0),
+            ([0.0, -12.0, 0.0], 2.0),
+            ([0.0, 1.0, 12.0], 5f32.sqrt())
+        ]);
+    }
+
+    #[test]
+    fn capsule_distance() {
+        let capsule = sdf(ColliderShape::Capsule { a: [0.0, 0.0, 0.0], b: [0.0, 10.0, 0.0], radius: 2.0 });
+        assert_distances(&capsule, &[
+            ([0.0, 5.0, 0.0], -2.0),
+            ([3.0, 5.0, 0.0], 1.0),
+            ([0.0, 13.0, 0.0], 1.0),
+            ([0.0, -1.0, 0.0], -1.0),
+            ([0.0, 2.0, -2.0], 0.0)
+        ]);
+    }
+
+    #[test]
+    fn cylinder_distance() {
+        let cylinder = sdf(ColliderShape::Cylinder { center: [0.0, 0.0, 0.0], axis: [0.0, 1.0, 0.0], radius: 3.0, height: 4.0 });
+        assert_distances(&cylinder, &[
+            ([0.0, 0.0, 0.0], -2.0),
+            ([0.0, 0.0, 2.5], -0.5),
+            ([5.0, 0.0, 0.0], 2.0),
+            ([0.0, 3.0, 0.0], 1.0),
+            ([4.0, 3.0, 0.0], 2f32.sqrt())
+        ]);
+    }
+
+    #[test]
+    fn torus_distance() {
+        let torus = sdf(ColliderShape::Torus { center: [0.0, 0.0, 0.0], axis: [0.0, 1.0, 0.0], major_radius: 10.0, minor_radius: 2.0 });
+        assert_distances(&torus, &[
+            ([10.0, 0.0, 0.0], -2.0),
+            ([0.0, 0.0, -11.0], -1.0),
+            ([0.0, 0.0, 0.0], 8.0),
+            ([10.0, 3.0, 0.0], 1.0),
+            ([13.0, 0.0, 0.0], 1.0)
+        ]);
+
+        //Around the x axis the ring lies in the yz plane.
+        let standing = sdf(ColliderShape::Torus { center: [0.0, 0.0, 0.0], axis: [1.0, 0.0, 0.0], major_radius: 10.0, minor_radius: 2.0 });
+        assert_distances(&standing, &[([0.0, 10.0, 0.0], -2.0), ([0.0, 0.0, 10.0], -2.0), ([10.0, 0.0, 0.0], 200f32.sqrt() - 2.0)]);
+    }
+
+    #[test]
+    fn collide_reflects_with_restitution_and_friction() {
+        let cuboid = ColliderShape::Box { center: [0.0, 0.0, 0.0], half_extents: [10.0, 10.0, 10.0], rotation: [0.0; 3] };
+        let sdf = collider(cuboid, 0.5, 0.5).sdf(&mut Vec::new());
+
+        //Half a unit below the top face: pushed out along its normal, the tangential part
+        //loses friction and the normal one is flipped and scaled by restitution.
+        let (mut position, mut velocity) = ([2.0, 9.5, 0.0], [4.0, -10.0, 0.0]);
+        assert!(sdf.collide(&mut position, &mut velocity, &mut false, &[], [0.0, 0.0]));
+        assert_vec3(position, [2.0, 10.1, 0.0]);
+        assert_vec3(velocity, [2.0, 5.0, 0.0]);
+
+        //Already leaving the surface, only pushed out.
+        let (mut position, mut velocity) = ([-9.5, 0.0, 0.0], [-3.0, 1.0, 0.0]);
+        assert!(sdf.collide(&mut position, &mut velocity, &mut false, &[], [0.0, 0.0]));
+        assert_vec3(position, [-10.1, 0.0, 0.0]);
+        assert_vec3(velocity, [-3.0, 1.0, 0.0]);
+
+        //Outside, left alone.
+        let (mut position, mut velocity) = ([0.0, 11.0, 0.0], [0.0, -10.0, 0.0]);
+        assert!(sdf.collide(&mut position, &mut velocity, &mut false, &[], [0.0, 0.0]));
+        assert_vec3(position, [0.0, 11.0, 0.0]);
+        assert_vec3(velocity, [0.0, -10.0, 0.0]);
+    }
+
+    #[test]
+    fn collide_with_a_killing_material() {
+        let ground = ColliderShape::Plane { point: [0.0, 0.0, 0.0], normal: [0.0, 1.0, 0.0], size: None, thickness: 10.0 };
+        let mut killer = collider(ground, 0.5, 0.5);
+        killer.kill = true;
+        let sdf = killer.sdf(&mut Vec::new());
+
+        let (mut position, mut velocity) = ([0.0, -1.0, 0.0], [0.0, -10.0, 0.0]);
+        assert!(!sdf.collide(&mut position, &mut velocity, &mut false, &[], [0.0, 0.0]));
+        let (mut position, mut velocity) = ([0.0, 1.0, 0.0], [0.0, -10.0, 0.0]);
+        assert!(sdf.collide(&mut position, &mut velocity, &mut false, &[], [0.0, 0.0]));
+    }
+
+    #[test]
+    fn animation_interpolates_between_keyframes() {
+        let keyframe = |time: f32, x: f32, yaw: f32| Keyframe { time, translation: [x, 0.0, 0.0], rotation: [0.0, yaw, 0.0] };
+        let mut animation = Animation { keyframes: vec![keyframe(1.0, 0.0, 0.0), keyframe(3.0, 10.0, 90.0)], repeat: false };
+
+        assert_eq!(animation.transform_at(2.0), ColliderTransform { translation: [5.0, 0.0, 0.0], rotation: [0.0, 45.0, 0.0] });
+        //Holds still before the first and after the last keyframe.
+        assert_eq!(animation.transform_at(0.0).translation, [0.0, 0.0, 0.0]);
+        assert_eq!(animation.transform_at(5.0).translation, [10.0, 0.0, 0.0]);
+
+        //repeat starts over from the first keyframe right after the last one.
+        animation.repeat = true;
+        assert_eq!(animation.transform_at(3.0).translation, [10.0, 0.0, 0.0]);
+        assert_eq!(animation.transform_at(5.0).translation, [0.0, 0.0, 0.0]);
+        assert_eq!(animation.transform_at(5.5).translation, [2.5, 0.0, 0.0]);
+        assert_eq!(animation.transform_at(8.0).translation, [5.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn surface_velocity_of_a_moving_collider() {
+        let cuboid = sdf(ColliderShape::Box { center: [0.0, 0.0, 0.0], half_extents: [1.0, 1.0, 1.0], rotation: [0.0; 3] });
+        //A tenth of a second moving 1 along x and turning 9 degrees around y.
+        let transform = ColliderTransform { translation: [1.0, 0.0, 0.0], rotation: [0.0, 9.0, 0.0] };
+        let mut moved = cuboid.transformed([0.0, 0.0, 0.0], &transform);
+        moved.set_motion(&cuboid, 0.1);
+
+        let spin = std::f32::consts::FRAC_PI_2;
+        assert_vec3(moved.velocity, [10.0, 0.0, 0.0]);
+        assert_vec3(moved.angular_velocity, [0.0, spin, 0.0]);
+        assert_vec3(moved.surface_velocity([1.0, 0.0, 0.0]), [10.0, 0.0, 0.0]);
+        assert_vec3(moved.surface_velocity([1.0, 0.0, 5.0]), [10.0 + 5.0 * spin, 0.0, 0.0]);
+        assert_vec3(moved.surface_velocity([6.0, 3.0, 0.0]), [10.0, 0.0, -5.0 * spin]);
+
+        //Without time passing there is no motion to measure.
+        let mut still = cuboid.transformed([0.0, 0.0, 0.0], &transform);
+        still.set_motion(&cuboid, 0.0);
+        assert_vec3(still.surface_velocity([6.0, 3.0, 0.0]), [0.0, 0.0, 0.0]);
+    }
+}
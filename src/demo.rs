@@ -0,0 +1,284 @@
+use gl;
+use std;
+use std::path::{Path, PathBuf};
+use camera::Camera;
+use emitter::VelocityMode;
+use graphics::capture;
+use graphics::shader::ShaderError;
+use particle_system::{GraphicsError, ParticleSystem};
+use renderer::Renderer;
+use scene::{Scene, SceneError};
+
+//Everything the demo binary does besides owning the window: command line options, the fixed
+//timestep loop, shader reloading for a whole scene, the particle count and burst keys and the
+//headless frame writer.
+
+pub const USAGE: &str = "Usage: rust_particles [--scene path] [--seed number] [--resolution WxH] [--headless [--frames N] [--out dir]]";
+
+//The simulation always advances in steps of FIXED_STEP seconds, the particles are drawn
+//between the last two steps (see ParticleSystem::set_interpolation).
+const FIXED_STEP: f64 = 1.0 / 60.0;
+//Longer frames (a breakpoint, a dragged window) don't make the simulation catch up.
+const MAX_FRAME_TIME: f64 = 0.25;
+
+//scale_particle_count keeps the total particle count within this range.
+const MIN_PARTICLES: usize = 100_000;
+const MAX_PARTICLES: usize = 16 * 1024 * 1024;
+
+//emit_burst sets off a burst of dead particles here.
+const BURST_POSITION: [f32; 3] = [0.0, 400.0, 0.0];
+const BURST_PARTICLES: u32 = 50_000;
+
+#[derive(Debug)]
+pub struct Options {
+    pub scene_path: Option<String>,
+    pub seed: Option<u64>,
+    pub headless: bool,
+    pub frames: u32,
+    pub out_dir: PathBuf,
+    pub resolution: (u32, u32)
+}
+
+
+impl Options {
+    //Parses the arguments after the program name.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Options, String> {
+        let mut options = Options {
+            scene_path: None,
+            seed: None,
+            headless: false,
+            frames: 1,
+            out_dir: PathBuf::from("frames"),
+            resolution: (1600, 900)
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--scene" => {
+                    options.scene_path = Some(args.next().ok_or("--scene expects a path")?);
+                },
+                "--seed" => {
+                    let value = args.next().ok_or("--seed expects a number")?;
+                    options.seed = Some(value.parse().map_err(|_| format!("Invalid seed: {}", value))?);
+                },
+                "--headless" => options.headless = true,
+                "--frames" => {
+                    let value = args.next().ok_or("--frames expects a number")?;
+                    options.frames = value.parse().map_err(|_| format!("Invalid frame count: {}", value))?;
+                },
+                "--out" => {
+                    options.out_dir = PathBuf::from(args.next().ok_or("--out expects a directory")?);
+                },
+                "--resolution" => {
+                    let value = args.next().ok_or("--resolution expects WIDTHxHEIGHT")?;
+                    options.resolution = parse_resolution(&value).ok_or_else(|| format!("Invalid resolution: {}", value))?;
+                },
+                _ => return Err(format!("Unknown argument: {}", arg))
+            }
+        }
+
+        Ok(options)
+    }
+
+    //The scene file, or the default scene, with --seed applied.
+    pub fn load_scene(&self) -> Result<Scene, SceneError> {
+        let mut scene = match self.scene_path {
+            Some(ref path) => Scene::load(path)?,
+            None => Scene::default()
+        };
+
+        if self.seed.is_some() {
+            scene.seed = self.seed;
+        }
+
+        Ok(scene)
+    }
+}
+
+
+pub fn init_graphics(particle_systems: &mut [ParticleSystem], width: u32, height: u32) -> Result<Renderer, GraphicsError> {
+    for system in particle_systems.iter_mut() {
+        system.init_graphics_resources()?;
+    }
+
+    Renderer::new(width, height)
+}
+
+
+pub fn render(renderer: &mut Renderer, particle_systems: &[ParticleSystem], cam: &Camera) {
+    renderer.render(particle_systems, cam);
+
+    unsafe {
+        gl::Flush();
+    }
+}
+
+
+//Runs as many FIXED_STEPs as accumulator plus frame_time allows and keeps the remainder
+//in accumulator for the next frame. A failed step is dropped, not retried next frame.
+pub fn step_simulation(particle_systems: &mut [ParticleSystem], accumulator: &mut f64, frame_time: f64) -> Result<(), GraphicsError> {
+    *accumulator += frame_time.min(MAX_FRAME_TIME);
+    while *accumulator >= FIXED_STEP {
+        *accumulator -= FIXED_STEP;
+        for system in particle_systems.iter_mut() {
+            system.update(FIXED_STEP)?;
+        }
+    }
+
+    let alpha = (*accumulator / FIXED_STEP) as f32;
+    for system in particle_systems.iter_mut() {
+        system.set_interpolation(alpha);
+    }
+
+    Ok(())
+}
+
+
+//Rebuilds the renderer's and every system's shaders. Systems share their shader files, so
+//an error is only reported once.
+pub fn load_shaders(renderer: &mut Renderer, particle_systems: &mut [ParticleSystem]) -> Result<(), Vec<ShaderError>> {
+    let mut results = vec![renderer.load_shaders()];
+    results.extend(particle_systems.iter_mut().map(|system| system.load_shaders()));
+
+    merge_shader_results(results)
+}
+
+
+//Same as load_shaders, but only for programs whose files changed. None when nothing changed.
+pub fn reload_changed_shaders(renderer: &mut Renderer, particle_systems: &mut [ParticleSystem]) -> Option<Result<(), Vec<ShaderError>>> {
+    let mut results: Vec<_> = renderer.reload_changed_shaders().into_iter().collect();
+    results.extend(particle_systems.iter_mut().filter_map(|system| system.reload_changed_shaders()));
+    if results.is_empty() {
+        return None;
+    }
+
+    Some(merge_shader_results(results))
+}
+
+
+//Doubles (grow) or halves the particle count of every system, unless that takes the total
+//out of [MIN_PARTICLES, MAX_PARTICLES].
+pub fn scale_particle_count(particle_systems: &mut [ParticleSystem], grow: bool) {
+    let total: usize = particle_systems.iter().map(|system| system.particle_count()).sum();
+    if (grow && total * 2 > MAX_PARTICLES) || (!grow && total / 2 < MIN_PARTICLES) {
+        return;
+    }
+
+    for system in particle_systems.iter_mut() {
+        let count = system.particle_count();
+        let count = if grow { count * 2 } else { (count / 2).max(1) };
+        if let Err(err) = system.set_particle_count(count) {
+            println!("Can't change the particle count: {}", err);
+        }
+    }
+    let total: usize = particle_systems.iter().map(|system| system.particle_count()).sum();
+    println!("Particle count: {}", total);
+}
+
+
+pub fn emit_burst(particle_system: &mut ParticleSystem) {
+    let velocity = VelocityMode::Radial { speed: [20.0, 80.0] };
+    if let Err(err) = particle_system.emit_burst(BURST_POSITION, BURST_PARTICLES, velocity) {
+        println!("Can't emit a burst: {}", err);
+    }
+}
+
+
+//Steps the simulation as if every frame took FIXED_STEP and writes every composited frame to out_dir.
+pub fn run_headless(
+    renderer: &mut Renderer,
+    particle_systems: &mut [ParticleSystem],
+    cam: &Camera,
+    width: u32,
+    height: u32,
+    frames: u32,
+    out_dir: &Path,
+) -> std::io::Result<()> {
+    let mut accumulator = 0.0;
+    std::fs::create_dir_all(out_dir)?;
+
+    for frame in 0..frames {
+        step_simulation(particle_systems, &mut accumulator, FIXED_STEP).map_err(|err| std::io::Error::other(err.to_string()))?;
+        render(renderer, particle_systems, cam);
+
+        let pixels = capture::read_pixels(width, height);
+        let path = out_dir.join(format!("frame_{:05}.png", frame));
+        capture::save_png(&path, width, height, &pixels)?;
+        println!("Wrote {}", path.display());
+    }
+
+    Ok(())
+}
+
+
+fn merge_shader_results(results: Vec<Result<(), Vec<ShaderError>>>) -> Result<(), Vec<ShaderError>> {
+    let mut messages = Vec::new();
+    let mut errors = Vec::new();
+    for err in results.into_iter().filter_map(Result::err).flatten() {
+        let message = err.to_string();
+        if !messages.contains(&message) {
+            messages.push(message);
+            errors.push(err);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+
+fn parse_resolution(value: &str) -> Option<(u32, u32)> {
+    let mut parts = value.splitn(2, 'x');
+    let width = parts.next()?.parse().ok()?;
+    let height = parts.next()?.parse().ok()?;
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    Some((width, height))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_every_option() {
+        let options = parse(&["--scene", "a.toml", "--seed", "3", "--headless", "--frames", "10", "--out", "dir", "--resolution", "640x480"]).unwrap();
+
+        assert_eq!(options.scene_path.as_deref(), Some("a.toml"));
+        assert_eq!(options.seed, Some(3));
+        assert!(options.headless);
+        assert_eq!(options.frames, 10);
+        assert_eq!(options.out_dir, PathBuf::from("dir"));
+        assert_eq!(options.resolution, (640, 480));
+    }
+
+    #[test]
+    fn defaults_without_arguments() {
+        let options = parse(&[]).unwrap();
+
+        assert_eq!(options.scene_path, None);
+        assert!(!options.headless);
+        assert_eq!(options.frames, 1);
+        assert_eq!(options.resolution, (1600, 900));
+    }
+
+    #[test]
+    fn rejects_bad_arguments() {
+        assert!(parse(&["--seed"]).is_err());
+        assert!(parse(&["--seed", "x"]).is_err());
+        assert!(parse(&["--resolution", "640"]).is_err());
+        assert!(parse(&["--resolution", "0x480"]).is_err());
+        assert!(parse(&["--fullscreen"]).is_err());
+    }
+}
@@ -0,0 +1,299 @@
+use cgmath::{InnerSpace, Vector3};
+use mesh::Triangle;
+
+//Signed distance to a closed triangle mesh sampled on a regular grid, negative inside.
+//Sampled in the compute shader by DistanceFieldGrid in shaders/colliders.glsl.
+#[derive(Debug, Clone)]
+pub struct DistanceGrid {
+    //World position of the first sample.
+    pub origin: [f32; 3],
+    //Distance between neighbouring samples.
+    pub voxel_size: f32,
+    //Samples along x, y and z, at least 2 each.
+    pub dims: [u32; 3],
+    //dims[0] * dims[1] * dims[2] distances, x changes fastest.
+    pub values: Vec<f32>
+}
+
+//Samples the grid reaches past the bounds of the mesh on every side, so particles hit the
+//surface before they run into the edge of the grid.
+const PADDING: u32 = 2;
+
+
+impl DistanceGrid {
+    //resolution is the number of samples along the longest side of the mesh bounds. The sign
+    //comes from counting surface crossings along x, so the mesh should be closed.
+    pub fn bake(triangles: &[Triangle], resolution: u32) -> DistanceGrid {
+        let mut min = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = -min;
+        for vertex in triangles.iter().flat_map(|triangle| triangle.iter()) {
+            for axis in 0..3 {
+                min[axis] = min[axis].min(vertex[axis]);
+                max[axis] = max[axis].max(vertex[axis]);
+            }
+        }
+
+        let extent = max - min;
+        let longest = extent.x.max(extent.y).max(extent.z).max(1e-3);
+        let voxel_size = longest / (resolution.max(2) - 1) as f32;
+        let padding = PADDING as f32 * voxel_size;
+        let origin = min - Vector3::new(padding, padding, padding);
+        let mut dims = [0u32; 3];
+        for axis in 0..3 {
+            dims[axis] = (extent[axis] / voxel_size).ceil() as u32 + 1 + 2 * PADDING;
+        }
+
+        let triangles: Vec<BakeTriangle> = triangles.iter()
+            .map(BakeTriangle::new)
+            .filter(|triangle| (triangle.b - triangle.a).cross(triangle.c - triangle.a).magnitude2() > 0.0)
+            .collect();
+
+        let mut values = Vec::with_capacity((dims[0] * dims[1] * dims[2]) as usize);
+        for k in 0..dims[2] {
+            for j in 0..dims[1] {
+                let y = origin.y + j as f32 * voxel_size;
+                let z = origin.z + k as f32 * voxel_size;
+                let crossings = row_crossings(&triangles, y, z, voxel_size);
+
+                //Distance is 1-Lipschitz, so the previous sample bounds the search for the next one.
+                let mut previous = f32::INFINITY;
+                for i in 0..dims[0] {
+                    let p = Vector3::new(origin.x + i as f32 * voxel_size, y, z);
+                    let distance = closest_distance(&triangles, p, previous + voxel_size);
+                    previous = distance;
+
+                    let inside = crossings.iter().take_while(|&&x| x < p.x).count() % 2 == 1;
+                    values.push(if inside { -distance } else { distance });
+                }
+            }
+        }
+
+        DistanceGrid {
+            origin: origin.into(),
+            voxel_size,
+            dims,
+            values
+        }
+    }
+
+    pub fn sample(&self, p: [f32; 3]) -> f32 {
+        sample(&self.values, self.origin, self.voxel_size, self.dims, p)
+    }
+}
+
+
+//Same as DistanceFieldGrid() in shaders/colliders.glsl: trilinear inside the grid, outside
+//it the distance to the grid plus the value on its border. values starts at the first
+//sample of the grid, see DistanceGrid for the layout.
+pub fn sample(values: &[f32], origin: [f32; 3], voxel_size: f32, dims: [u32; 3], p: [f32; 3]) -> f32 {
+    let mut outside = Vector3::new(0.0, 0.0, 0.0);
+    let mut index = [0usize; 3];
+    let mut fraction = [0.0f32; 3];
+    for axis in 0..3 {
+        let last = (dims[axis] - 1) as f32 * voxel_size;
+        let local = p[axis] - origin[axis];
+        let clamped = local.max(0.0).min(last);
+        outside[axis] = local - clamped;
+
+        let g = clamped / voxel_size;
+        let i = (g.floor() as usize).min(dims[axis] as usize - 2);
+        index[axis] = i;
+        fraction[axis] = g - i as f32;
+    }
+
+    let value = |x: usize, y: usize, z: usize| {
+        let (dx, dy) = (dims[0] as usize, dims[1] as usize);
+        values[index[0] + x + dx * (index[1] + y + dy * (index[2] + z))]
+    };
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let x00 = lerp(value(0, 0, 0), value(1, 0, 0), fraction[0]);
+    let x10 = lerp(value(0, 1, 0), value(1, 1, 0), fraction[0]);
+    let x01 = lerp(value(0, 0, 1), value(1, 0, 1), fraction[0]);
+    let x11 = lerp(value(0, 1, 1), value(1, 1, 1), fraction[0]);
+    let inside = lerp(lerp(x00, x10, fraction[1]), lerp(x01, x11, fraction[1]), fraction[2]);
+
+    outside.magnitude() + inside
+}
+
+
+struct BakeTriangle {
+    a: Vector3<f32>,
+    b: Vector3<f32>,
+    c: Vector3<f32>,
+    min: Vector3<f32>,
+    max: Vector3<f32>
+}
+
+
+impl BakeTriangle {
+    fn new(triangle: &Triangle) -> BakeTriangle {
+        let (a, b, c) = (Vector3::from(triangle[0]), Vector3::from(triangle[1]), Vector3::from(triangle[2]));
+        BakeTriangle {
+            a,
+            b,
+            c,
+            min: Vector3::new(a.x.min(b.x).min(c.x), a.y.min(b.y).min(c.y), a.z.min(b.z).min(c.z)),
+            max: Vector3::new(a.x.max(b.x).max(c.x), a.y.max(b.y).max(c.y), a.z.max(b.z).max(c.z))
+        }
+    }
+
+    //Squared distance from p to the bounding box, a lower bound for the distance to the triangle.
+    fn box_distance2(&self, p: Vector3<f32>) -> f32 {
+        let mut sum = 0.0;
+        for axis in 0..3 {
+            let d = (self.min[axis] - p[axis]).max(p[axis] - self.max[axis]).max(0.0);
+            sum += d * d;
+        }
+        sum
+    }
+
+    //Closest point on the triangle, from Ericson's Real-Time Collision Detection.
+    fn closest_point(&self, p: Vector3<f32>) -> Vector3<f32> {
+        let (a, b, c) = (self.a, self.b, self.c);
+        let (ab, ac, ap) = (b - a, c - a, p - a);
+        let (d1, d2) = (ab.dot(ap), ac.dot(ap));
+        if d1 <= 0.0 && d2 <= 0.0 {
+            return a;
+        }
+
+        let bp = p - b;
+        let (d3, d4) = (ab.dot(bp), ac.dot(bp));
+        if d3 >= 0.0 && d4 <= d3 {
+            return b;
+        }
+
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+            return a + ab * (d1 / (d1 - d3));
+        }
+
+        let cp = p - c;
+        let (d5, d6) = (ab.dot(cp), ac.dot(cp));
+        if d6 >= 0.0 && d5 <= d6 {
+            return c;
+        }
+
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+            return a + ac * (d2 / (d2 - d6));
+        }
+
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+            return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+        }
+
+        let denom = 1.0 / (va + vb + vc);
+        a + ab * (vb * denom) + ac * (vc * denom)
+    }
+}
+
+
+//Distance from p to the closest triangle, looking only at triangles closer than limit (the
+//result is never above limit when limit is finite).
+fn closest_distance(triangles: &[BakeTriangle], p: Vector3<f32>, limit: f32) -> f32 {
+    let mut best2 = limit * limit;
+    for triangle in triangles {
+        if triangle.box_distance2(p) < best2 {
+            best2 = best2.min((triangle.closest_point(p) - p).magnitude2());
+        }
+    }
+
+    best2.sqrt()
+}
+
+
+//Sorted x positions where the line through (y, z) along x crosses the surface. The line is
+//nudged off the sample position so it doesn't run exactly through shared edges and vertices.
+fn row_crossings(triangles: &[BakeTriangle], y: f32, z: f32, voxel_size: f32) -> Vec<f32> {
+    let (y, z) = (y + voxel_size * 1.37e-3, z + voxel_size * 2.11e-3);
+
+    let mut crossings = Vec::new();
+    for triangle in triangles {
+        if y < triangle.min.y || y > triangle.max.y || z < triangle.min.z || z > triangle.max.z {
+            continue;
+        }
+
+        //Barycentric coordinates of (y, z) in the triangle projected onto the yz plane.
+        let edge = |from: Vector3<f32>, to: Vector3<f32>| (to.y - from.y) * (z - from.z) - (to.z - from.z) * (y - from.y);
+        let (wa, wb, wc) = (edge(triangle.b, triangle.c), edge(triangle.c, triangle.a), edge(triangle.a, triangle.b));
+        let inside = (wa > 0.0 && wb > 0.0 && wc > 0.0) || (wa < 0.0 && wb < 0.0 && wc < 0.0);
+        if inside {
+            let sum = wa + wb + wc;
+            crossings.push((triangle.a.x * wa + triangle.b.x * wb + triangle.c.x * wc) / sum);
+        }
+    }
+
+    crossings.sort_by(|a, b| a.partial_cmp(b).expect("crossings are finite"));
+    crossings
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //Closed cube from -1 to 1, every face split along the diagonal where its two other
+    //coordinates are equal. Rows along x with y == z run right through those diagonals.
+    fn cube() -> Vec<Triangle> {
+        let mut triangles = Vec::new();
+        for axis in 0..3 {
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            for &side in &[-1.0, 1.0] {
+                let corner = |a: f32, b: f32| {
+                    let mut p = [0.0; 3];
+                    p[axis] = side;
+                    p[u] = a;
+                    p[v] = b;
+                    p
+                };
+                let corners = [corner(-1.0, -1.0), corner(1.0, -1.0), corner(1.0, 1.0), corner(-1.0, 1.0)];
+                triangles.push([corners[0], corners[1], corners[2]]);
+                triangles.push([corners[0], corners[2], corners[3]]);
+            }
+        }
+
+        triangles
+    }
+
+    fn cube_distance(p: [f32; 3]) -> f32 {
+        let d = Vector3::new(p[0].abs() - 1.0, p[1].abs() - 1.0, p[2].abs() - 1.0);
+        Vector3::new(d.x.max(0.0), d.y.max(0.0), d.z.max(0.0)).magnitude() + d.x.max(d.y).max(d.z).min(0.0)
+    }
+
+    #[test]
+    fn bakes_a_closed_cube() {
+        let grid = DistanceGrid::bake(&cube(), 9);
+        assert_eq!(grid.voxel_size, 0.25);
+        assert_eq!(grid.origin, [-1.5, -1.5, -1.5]);
+        assert_eq!(grid.dims, [13, 13, 13]);
+
+        //Samples on shared edges and vertices, and rows through them, must not be counted
+        //twice: every sample has the sign and the magnitude of the exact distance.
+        for k in 0..13 {
+            for j in 0..13 {
+                for i in 0..13 {
+                    let p = [-1.5 + i as f32 * 0.25, -1.5 + j as f32 * 0.25, -1.5 + k as f32 * 0.25];
+                    let actual = grid.values[i + 13 * (j + 13 * k)];
+                    let expected = cube_distance(p);
+                    assert!((actual - expected).abs() < 1e-4, "{} at {:?}, expected {}", actual, p, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn samples_between_and_outside_the_samples() {
+        let grid = DistanceGrid::bake(&cube(), 9);
+
+        //Inside, outside and close to the surface, away from the edges the field is linear.
+        for &p in &[[0.1, 0.2, 0.3], [0.1, 0.95, -0.2], [0.3, 1.05, 0.1], [-1.3, 0.4, 0.2], [0.0, 0.0, 0.0]] {
+            assert!((grid.sample(p) - cube_distance(p)).abs() < 1e-4, "{} at {:?}", grid.sample(p), p);
+        }
+
+        //Past the grid the distance to it is added to its border value.
+        assert!((grid.sample([5.0, 0.0, 0.0]) - 4.0).abs() < 1e-4);
+        assert!((grid.sample([5.0, 5.0, 0.0]) - 32f32.sqrt()).abs() < 1e-4);
+        assert!((grid.sample([0.0, -3.0, 0.0]) - 2.0).abs() < 1e-4);
+    }
+}
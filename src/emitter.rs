@@ -0,0 +1,637 @@
+use graphics::shader::ShaderProgram;
+use std::f32::consts::PI;
+use std::path::Path;
+use mesh;
+
+//Where and how dead particles come back. The shape picks the position, the velocity mode
+//the initial velocity. Mirrored by shaders/emitter.glsl, keep the two in sync.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Emitter {
+    pub shape: EmitterShape,
+    pub velocity: VelocityMode,
+    //Particles respawned per second, None brings every dead particle back right away.
+    pub rate: Option<f32>,
+    //[min, max] seconds a particle lives before it is recycled, each particle gets a fixed
+    //value in the range. None keeps particles alive until they land or come to rest.
+    pub lifetime: Option<[f32; 2]>
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum EmitterShape {
+    Point {
+        position: [f32; 3]
+    },
+    Sphere {
+        center: [f32; 3],
+        radius: f32,
+        //Emit from the surface only instead of the whole volume.
+        #[serde(default)]
+        surface: bool
+    },
+    Box {
+        min: [f32; 3],
+        max: [f32; 3]
+    },
+    Disc {
+        center: [f32; 3],
+        normal: [f32; 3],
+        radius: f32
+    },
+    //Volume of a cone with its tip at apex, opening along direction. angle is the half
+    //angle in degrees.
+    Cone {
+        apex: [f32; 3],
+        direction: [f32; 3],
+        angle: f32,
+        height: f32
+    },
+    //Surface of a triangle mesh (OBJ or glTF), sampled uniformly by area. The file is read by
+    //load_mesh, scaled by scale and moved to position. load_mesh also fills in the area of
+    //every triangle and all the previous ones over the total area and the average vertex,
+    //so emitting a particle doesn't walk the whole mesh.
+    Mesh {
+        path: String,
+        #[serde(default)]
+        position: [f32; 3],
+        #[serde(default = "default_scale")]
+        scale: f32,
+        #[serde(skip)]
+        triangles: Vec<[[f32; 3]; 3]>,
+        #[serde(skip)]
+        cumulative_areas: Vec<f32>,
+        #[serde(skip)]
+        center: [f32; 3]
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum VelocityMode {
+    //Every component picked independently in [min, max].
+    Range {
+        min: [f32; 3],
+        max: [f32; 3]
+    },
+    //Away from the center of the shape (the apex for cones), speed in [min, max].
+    Radial {
+        speed: [f32; 2]
+    },
+    //Along direction, tilted by up to spread degrees, speed in [min, max].
+    Directional {
+        direction: [f32; 3],
+        spread: f32,
+        speed: [f32; 2]
+    }
+}
+
+//Values of the emitterShape and velocityMode uniforms, see shaders/emitter.glsl.
+const SHAPE_BOX: i32 = 0;
+const SHAPE_POINT: i32 = 1;
+const SHAPE_SPHERE: i32 = 2;
+const SHAPE_SPHERE_SURFACE: i32 = 3;
+const SHAPE_DISC: i32 = 4;
+const SHAPE_CONE: i32 = 5;
+const SHAPE_MESH: i32 = 6;
+const VELOCITY_RANGE: i32 = 0;
+const VELOCITY_RADIAL: i32 = 1;
+const VELOCITY_DIRECTIONAL: i32 = 2;
+
+
+impl Emitter {
+    pub fn validate(&self) -> Result<(), String> {
+        match self.shape {
+            EmitterShape::Point { .. } => {}
+            EmitterShape::Sphere { radius, .. } => check_positive("sphere radius", radius)?,
+            EmitterShape::Box { min, max } => {
+                if min.iter().zip(max.iter()).any(|(a, b)| a > b) {
+                    return Err(format!("emitter box min {:?} is above max {:?}", min, max));
+                }
+            }
+            EmitterShape::Disc { normal, radius, .. } => {
+                check_direction("disc normal", normal)?;
+                check_positive("disc radius", radius)?;
+            }
+            EmitterShape::Cone { direction, angle, height, .. } => {
+                check_direction("cone direction", direction)?;
+                check_positive("cone height", height)?;
+                if angle <= 0.0 || angle >= 90.0 {
+                    return Err(format!("cone angle {} has to be between 0 and 90 degrees", angle));
+                }
+            }
+            EmitterShape::Mesh { scale, .. } => check_positive("mesh scale", scale)?
+        }
+
+        self.velocity.validate()?;
+
+        if let Some(rate) = self.rate {
+            check_positive("emitter rate", rate)?;
+        }
+        if let Some(lifetime) = self.lifetime {
+            if lifetime[0] <= 0.0 || lifetime[0] > lifetime[1] {
+                return Err(format!("emitter lifetime {:?} has to be a positive [min, max] range", lifetime));
+            }
+        }
+
+        Ok(())
+    }
+
+    //Reads the triangles of a Mesh shape, path is relative to base_dir. Other shapes are left alone.
+    pub fn load_mesh(&mut self, base_dir: &Path) -> Result<(), String> {
+        if let EmitterShape::Mesh { ref path, position, scale: mesh_scale, ref mut triangles, ref mut cumulative_areas, ref mut center } = self.shape {
+            let full_path = base_dir.join(path);
+            *triangles = mesh::load_triangles(&full_path)
+                .map_err(|err| format!("can't load emitter mesh {}: {}", full_path.display(), err))?;
+            mesh::place(triangles, position, mesh_scale);
+
+            let total: f32 = triangles.iter().map(triangle_area).sum();
+            if total <= 0.0 {
+                return Err(format!("emitter mesh {} has no surface", full_path.display()));
+            }
+
+            let mut cumulative = 0.0;
+            *cumulative_areas = triangles.iter().map(|triangle| {
+                cumulative += triangle_area(triangle) / total;
+                cumulative
+            }).collect();
+
+            let mut sum = [0.0; 3];
+            for vertex in triangles.iter().flat_map(|triangle| triangle.iter()) {
+                sum = add(sum, *vertex);
+            }
+            *center = scale(sum, 1.0 / (triangles.len() * 3) as f32);
+        }
+
+        Ok(())
+    }
+
+    //Point radial velocities move away from.
+    pub fn center(&self) -> [f32; 3] {
+        match self.shape {
+            EmitterShape::Point { position } => position,
+            EmitterShape::Sphere { center, .. } | EmitterShape::Disc { center, .. } => center,
+            EmitterShape::Box { min, max } => [(min[0] + max[0]) * 0.5, (min[1] + max[1]) * 0.5, (min[2] + max[2]) * 0.5],
+            EmitterShape::Cone { apex, .. } | EmitterShape::Mesh { center: apex, .. } => apex,
+        }
+    }
+
+    //Lowest y a particle can be emitted at.
+    pub fn floor(&self) -> f32 {
+        match self.shape {
+            EmitterShape::Point { position } => position[1],
+            EmitterShape::Sphere { center, radius, .. } => center[1] - radius,
+            EmitterShape::Box { min, .. } => min[1],
+            EmitterShape::Disc { center, normal, radius } => {
+                let n = normalize(normal);
+                center[1] - radius * (1.0 - n[1] * n[1]).max(0.0).sqrt()
+            }
+            EmitterShape::Cone { apex, direction, angle, height } => {
+                let d = normalize(direction);
+                let base_radius = height * angle.to_radians().tan();
+                let base = apex[1] + d[1] * height - base_radius * (1.0 - d[1] * d[1]).max(0.0).sqrt();
+                apex[1].min(base)
+            }
+            EmitterShape::Mesh { ref triangles, .. } => triangles.iter()
+                .flat_map(|triangle| triangle.iter())
+                .map(|vertex| vertex[1])
+                .fold(f32::INFINITY, f32::min)
+        }
+    }
+
+    //Triangles as the EmitterMesh buffer expects them: three vec4 corners per triangle, the
+    //first w holds the area of this and all the previous triangles over the total area.
+    pub fn mesh_buffer_data(&self) -> Vec<f32> {
+        let (triangles, cumulative_areas) = match self.shape {
+            EmitterShape::Mesh { ref triangles, ref cumulative_areas, .. } => (triangles, cumulative_areas),
+            _ => return Vec::new()
+        };
+
+        let mut data = Vec::with_capacity(triangles.len() * 12);
+        for (triangle, &cumulative) in triangles.iter().zip(cumulative_areas) {
+            for (i, vertex) in triangle.iter().enumerate() {
+                data.extend_from_slice(vertex);
+                data.push(if i == 0 { cumulative } else { 0.0 });
+            }
+        }
+
+        data
+    }
+
+    pub fn set_uniforms(&self, program: &ShaderProgram) {
+        let (shape, a, b, params) = match self.shape {
+            EmitterShape::Box { min, max } => (SHAPE_BOX, min, max, [0.0; 3]),
+            EmitterShape::Point { position } => (SHAPE_POINT, position, [0.0; 3], [0.0; 3]),
+            EmitterShape::Sphere { center, radius, surface } => {
+                let shape = if surface { SHAPE_SPHERE_SURFACE } else { SHAPE_SPHERE };
+                (shape, center, [0.0; 3], [radius, 0.0, 0.0])
+            }
+            EmitterShape::Disc { center, normal, radius } => (SHAPE_DISC, center, normalize(normal), [radius, 0.0, 0.0]),
+            EmitterShape::Cone { apex, direction, angle, height } =>
+                (SHAPE_CONE, apex, normalize(direction), [angle.to_radians(), height, 0.0]),
+            EmitterShape::Mesh { ref triangles, .. } => (SHAPE_MESH, [0.0; 3], [0.0; 3], [triangles.len() as f32, 0.0, 0.0])
+        };
+        program.set_uniform_1i("emitterShape", shape);
+        program.set_uniform_3f("emitterA", &a);
+        program.set_uniform_3f("emitterB", &b);
+        program.set_uniform_3f("emitterParams", &params);
+        program.set_uniform_3f("emitterCenter", &self.center());
+        program.set_uniform_1f("emitterFloor", self.floor());
+
+        let (mode, a, b, params) = self.velocity.shader_values();
+        program.set_uniform_1i("velocityMode", mode);
+        program.set_uniform_3f("velocityA", &a);
+        program.set_uniform_3f("velocityB", &b);
+        program.set_uniform_3f("velocityParams", &params);
+    }
+
+    //CPU version of emitPosition()/emitVelocity(). rand1/rand2 are the [0, 1000) hashes the
+    //box and range modes use, u and v are uniform [0, 1) values.
+    pub fn emit(&self, rand1: f32, rand2: f32, u: [f32; 4], v: [f32; 4]) -> ([f32; 3], [f32; 3]) {
+        let position = match self.shape {
+            EmitterShape::Box { min, max } => [
+                min[0] + spawn_offset(rand1 * 10.0, max[0] - min[0]),
+                min[1] + spawn_offset(rand1, max[1] - min[1]),
+                min[2] + spawn_offset(rand2 * 10.0, max[2] - min[2])
+            ],
+            EmitterShape::Point { position } => position,
+            EmitterShape::Sphere { center, radius, surface } => {
+                let r = if surface { radius } else { radius * u[2].cbrt() };
+                add(center, scale(unit_vector(u[0], u[1]), r))
+            }
+            EmitterShape::Disc { center, normal, radius } => {
+                let (t, b) = orthonormal_basis(normalize(normal));
+                add(center, scale(circle_point(t, b, u[1]), radius * u[0].sqrt()))
+            }
+            EmitterShape::Cone { apex, direction, angle, height } => {
+                let d = normalize(direction);
+                let (t, b) = orthonormal_basis(d);
+                let h = height * u[0].cbrt();
+                let r = h * angle.to_radians().tan() * u[1].sqrt();
+                add(add(apex, scale(d, h)), scale(circle_point(t, b, u[2]), r))
+            }
+            EmitterShape::Mesh { .. } => self.sample_mesh(u)
+        };
+
+        (position, self.velocity.sample(position, self.center(), rand1, rand2, v))
+    }
+
+    //Same binary search over the cumulative areas as sampleMesh() in shaders/emitter.glsl.
+    fn sample_mesh(&self, u: [f32; 4]) -> [f32; 3] {
+        let (triangles, cumulative_areas) = match self.shape {
+            EmitterShape::Mesh { ref triangles, ref cumulative_areas, .. } => (triangles, cumulative_areas),
+            _ => return [0.0; 3]
+        };
+        if cumulative_areas.is_empty() {
+            return [0.0; 3];
+        }
+
+        let index = cumulative_areas.partition_point(|&area| area < u[0]).min(cumulative_areas.len() - 1);
+        let triangle = &triangles[index];
+        let s = u[1].sqrt();
+        add(add(scale(triangle[0], 1.0 - s), scale(triangle[1], s * (1.0 - u[2]))), scale(triangle[2], s * u[2]))
+    }
+}
+
+
+impl VelocityMode {
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            VelocityMode::Range { min, max } => {
+                if min.iter().zip(max.iter()).any(|(a, b)| a > b) {
+                    return Err(format!("velocity min {:?} is above max {:?}", min, max));
+                }
+            }
+            VelocityMode::Radial { speed } => check_speed(speed)?,
+            VelocityMode::Directional { direction, spread, speed } => {
+                check_direction("velocity direction", direction)?;
+                check_speed(speed)?;
+                if !(0.0..=180.0).contains(&spread) {
+                    return Err(format!("velocity spread {} has to be between 0 and 180 degrees", spread));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    //Mode, a, b and params as sampleVelocity() in shaders/emitter.glsl takes them.
+    pub fn shader_values(&self) -> (i32, [f32; 3], [f32; 3], [f32; 3]) {
+        match *self {
+            VelocityMode::Range { min, max } => (VELOCITY_RANGE, min, max, [0.0; 3]),
+            VelocityMode::Radial { speed } => (VELOCITY_RADIAL, [0.0; 3], [0.0; 3], [speed[0], speed[1], 0.0]),
+            VelocityMode::Directional { direction, spread, speed } =>
+                (VELOCITY_DIRECTIONAL, normalize(direction), [0.0; 3], [speed[0], speed[1], spread.to_radians()])
+        }
+    }
+
+    //CPU version of sampleVelocity(), radial velocities move away from center.
+    pub fn sample(&self, position: [f32; 3], center: [f32; 3], rand1: f32, rand2: f32, v: [f32; 4]) -> [f32; 3] {
+        match *self {
+            VelocityMode::Range { min, max } => [
+                min[0] + spawn_offset(rand2 * 100.0, max[0] - min[0]),
+                min[1] + spawn_offset(rand2 * 100.0, max[1] - min[1]),
+                min[2] + spawn_offset(rand1 * 100.0, max[2] - min[2])
+            ],
+            VelocityMode::Radial { speed } => {
+                //Emitted right at the center, any direction will do.
+                let away = sub(position, center);
+                let direction = if length(away) > 0.0001 { normalize(away) } else { unit_vector(v[0], v[1]) };
+                scale(direction, speed[0] + (speed[1] - speed[0]) * v[3])
+            }
+            VelocityMode::Directional { direction, spread, speed } => {
+                let d = normalize(direction);
+                let (t, b) = orthonormal_basis(d);
+                let cos_theta = 1.0 + (spread.to_radians().cos() - 1.0) * v[0];
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let tilted = add(scale(d, cos_theta), scale(circle_point(t, b, v[1]), sin_theta));
+                scale(tilted, speed[0] + (speed[1] - speed[0]) * v[3])
+            }
+        }
+    }
+}
+
+
+impl Default for Emitter {
+    fn default() -> Emitter {
+        Emitter {
+            shape: EmitterShape::Box {
+                min: [-700.0, 500.0, -700.0],
+                max: [700.0, 550.0, 700.0]
+            },
+            velocity: VelocityMode::Range {
+                min: [-5.0, -5.0, -5.0],
+                max: [5.0, 5.0, 5.0]
+            },
+            rate: None,
+            lifetime: None
+        }
+    }
+}
+
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+
+fn check_positive(name: &str, value: f32) -> Result<(), String> {
+    if value > 0.0 {
+        Ok(())
+    } else {
+        Err(format!("{} has to be positive, got {}", name, value))
+    }
+}
+
+
+fn check_direction(name: &str, direction: [f32; 3]) -> Result<(), String> {
+    if length(direction) > 0.0 {
+        Ok(())
+    } else {
+        Err(format!("{} can't be a zero vector", name))
+    }
+}
+
+
+fn check_speed(speed: [f32; 2]) -> Result<(), String> {
+    if speed[0] < 0.0 || speed[0] > speed[1] {
+        return Err(format!("speed {:?} has to be a non negative [min, max] range", speed));
+    }
+
+    Ok(())
+}
+
+
+//Same as random4() in shaders/common.glsl.
+#[allow(clippy::excessive_precision)]
+pub fn random4(x: f32, y: f32) -> [f32; 4] {
+    let hash = |a: f32, b: f32| {
+        let v = (x * a + y * b).sin() * 43758.5453;
+        v - v.floor()
+    };
+
+    [hash(12.9898, 78.233), hash(39.3468, 11.1353), hash(73.156, 52.235), hash(94.673, 27.374)]
+}
+
+
+//Same as spawnOffset() in shaders/common.glsl, a flat spawn range always yields its minimum.
+pub fn spawn_offset(r: f32, size: f32) -> f32 {
+    if size > 0.0 { glsl_mod(r, size) } else { 0.0 }
+}
+
+
+//GLSL mod() rounds towards negative infinity, unlike the % operator.
+fn glsl_mod(x: f32, y: f32) -> f32 {
+    x - y * (x / y).floor()
+}
+
+
+fn triangle_area(triangle: &[[f32; 3]; 3]) -> f32 {
+    length(cross(sub(triangle[1], triangle[0]), sub(triangle[2], triangle[0]))) * 0.5
+}
+
+
+//Uniformly distributed direction.
+fn unit_vector(u: f32, v: f32) -> [f32; 3] {
+    let z = 2.0 * u - 1.0;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    [r * phi.cos(), r * phi.sin(), z]
+}
+
+
+fn circle_point(t: [f32; 3], b: [f32; 3], u: f32) -> [f32; 3] {
+    let phi = 2.0 * PI * u;
+    add(scale(t, phi.cos()), scale(b, phi.sin()))
+}
+
+
+//Two unit vectors perpendicular to n and to each other.
+fn orthonormal_basis(n: [f32; 3]) -> ([f32; 3], [f32; 3]) {
+    let up = if n[1].abs() < 0.99 { [0.0, 1.0, 0.0] } else { [1.0, 0.0, 0.0] };
+    let t = normalize(cross(up, n));
+    (t, cross(n, t))
+}
+
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+
+fn scale(a: [f32; 3], s: f32) -> [f32; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+
+fn length(a: [f32; 3]) -> f32 {
+    (a[0] * a[0] + a[1] * a[1] + a[2] * a[2]).sqrt()
+}
+
+
+fn normalize(a: [f32; 3]) -> [f32; 3] {
+    scale(a, 1.0 / length(a))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const SAMPLES: usize = 2000;
+
+    fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+        a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+    }
+
+    //Positions and velocities emitted for SAMPLES hash inputs, the way update_cpu feeds emit.
+    fn emitted(emitter: &Emitter) -> Vec<([f32; 3], [f32; 3])> {
+        (0..SAMPLES).map(|i| {
+            let (x, z) = (i as f32 * 1.37, i as f32 * 0.71 + 3.0);
+            let u = random4(x, z);
+            let v = random4(z, x);
+            emitter.emit(u[0] * 1000.0, v[0] * 1000.0, u, v)
+        }).collect()
+    }
+
+    fn emitter_with(shape: EmitterShape, velocity: VelocityMode) -> Emitter {
+        Emitter { shape, velocity, ..Emitter::default() }
+    }
+
+    fn still() -> VelocityMode {
+        VelocityMode::Range { min: [0.0; 3], max: [0.0; 3] }
+    }
+
+    #[test]
+    fn box_and_range_stay_within_their_limits() {
+        let velocity = VelocityMode::Range { min: [-1.0, 2.0, -3.0], max: [1.0, 4.0, 3.0] };
+        let emitter = emitter_with(EmitterShape::Box { min: [-10.0, 0.0, 5.0], max: [10.0, 1.0, 6.0] }, velocity);
+
+        for (position, velocity) in emitted(&emitter) {
+            assert!(position[0] >= -10.0 && position[0] <= 10.0, "{:?}", position);
+            assert!(position[1] >= 0.0 && position[1] <= 1.0, "{:?}", position);
+            assert!(position[2] >= 5.0 && position[2] <= 6.0, "{:?}", position);
+            assert!(velocity[0] >= -1.0 && velocity[0] <= 1.0, "{:?}", velocity);
+            assert!(velocity[1] >= 2.0 && velocity[1] <= 4.0, "{:?}", velocity);
+            assert!(velocity[2] >= -3.0 && velocity[2] <= 3.0, "{:?}", velocity);
+        }
+    }
+
+    #[test]
+    fn sphere_fills_the_volume_or_covers_the_surface() {
+        let center = [10.0, 20.0, 30.0];
+        let volume = emitter_with(EmitterShape::Sphere { center, radius: 5.0, surface: false }, still());
+        let distances: Vec<f32> = emitted(&volume).iter().map(|&(position, _)| length(sub(position, center))).collect();
+        assert!(distances.iter().all(|&distance| distance <= 5.0 + 1e-4), "{:?}", distances);
+        //Uniform in the volume puts half the particles beyond 0.5^(1/3) of the radius.
+        let outer = distances.iter().filter(|&&distance| distance > 5.0 * 0.5f32.cbrt()).count() as f32 / SAMPLES as f32;
+        assert!((outer - 0.5).abs() < 0.05, "{}", outer);
+
+        let surface = emitter_with(EmitterShape::Sphere { center, radius: 5.0, surface: true }, still());
+        for (position, _) in emitted(&surface) {
+            assert!((length(sub(position, center)) - 5.0).abs() < 1e-4, "{:?}", position);
+        }
+    }
+
+    #[test]
+    fn disc_stays_in_its_plane() {
+        let (center, normal) = ([0.0, 100.0, 0.0], [1.0, 1.0, 0.0]);
+        let emitter = emitter_with(EmitterShape::Disc { center, normal, radius: 50.0 }, still());
+
+        for (position, _) in emitted(&emitter) {
+            let offset = sub(position, center);
+            assert!(dot(offset, normalize(normal)).abs() < 1e-3, "{:?}", position);
+            assert!(length(offset) <= 50.0 + 1e-3, "{:?}", position);
+        }
+    }
+
+    #[test]
+    fn cone_stays_within_its_half_angle() {
+        let (apex, direction) = ([0.0, 10.0, 0.0], [0.0, 0.0, 2.0]);
+        let emitter = emitter_with(EmitterShape::Cone { apex, direction, angle: 30.0, height: 40.0 }, still());
+
+        for (position, _) in emitted(&emitter) {
+            let offset = sub(position, apex);
+            let along = dot(offset, [0.0, 0.0, 1.0]);
+            assert!((0.0..=40.0 + 1e-3).contains(&along), "{:?}", position);
+            if length(offset) < 1e-3 {
+                continue;
+            }
+            let angle = (along / length(offset)).acos().to_degrees();
+            assert!(angle <= 30.0 + 1e-2, "{:?} is {} degrees off the axis", position, angle);
+        }
+    }
+
+    #[test]
+    fn mesh_is_sampled_by_area() {
+        //Two triangles in the y = 0 plane, the second one three times the size of the first.
+        let dir = std::env::temp_dir().join(format!("rust_particles_emitter_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("two.obj"), "v 0 0 0\nv 1 0 0\nv 0 0 2\nv 10 0 0\nv 13 0 0\nv 10 0 2\nf 1 2 3\nf 4 5 6\n").unwrap();
+
+        let shape = EmitterShape::Mesh {
+            path: "two.obj".to_string(),
+            position: [0.0, 5.0, 0.0],
+            scale: 1.0,
+            triangles: Vec::new(),
+            cumulative_areas: Vec::new(),
+            center: [0.0; 3]
+        };
+        let mut emitter = emitter_with(shape, still());
+        emitter.load_mesh(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        match emitter.shape {
+            EmitterShape::Mesh { ref cumulative_areas, center, .. } => {
+                assert_eq!(cumulative_areas, &vec![0.25, 1.0]);
+                assert!(length(sub(center, [34.0 / 6.0, 5.0, 4.0 / 6.0])) < 1e-5, "{:?}", center);
+            }
+            _ => unreachable!()
+        }
+        assert_eq!(emitter.floor(), 5.0);
+
+        let positions: Vec<[f32; 3]> = emitted(&emitter).iter().map(|&(position, _)| position).collect();
+        for position in &positions {
+            assert!((position[1] - 5.0).abs() < 1e-4, "{:?}", position);
+        }
+        let large = positions.iter().filter(|position| position[0] >= 10.0).count() as f32 / SAMPLES as f32;
+        assert!((large - 0.75).abs() < 0.05, "{}", large);
+    }
+
+    #[test]
+    fn radial_velocity_points_away_from_the_center() {
+        let center = [0.0, 50.0, 0.0];
+        let velocity = VelocityMode::Radial { speed: [2.0, 3.0] };
+        let emitter = emitter_with(EmitterShape::Sphere { center, radius: 10.0, surface: true }, velocity);
+
+        for (position, velocity) in emitted(&emitter) {
+            let speed = length(velocity);
+            assert!((2.0 - 1e-4..=3.0 + 1e-4).contains(&speed), "{:?}", velocity);
+            let away = normalize(sub(position, center));
+            assert!(dot(away, velocity) / speed > 0.9999, "{:?} at {:?}", velocity, position);
+        }
+    }
+
+    #[test]
+    fn directional_velocity_stays_within_the_spread() {
+        let velocity = VelocityMode::Directional { direction: [0.0, 3.0, 0.0], spread: 20.0, speed: [5.0, 5.0] };
+        let emitter = emitter_with(EmitterShape::Point { position: [0.0; 3] }, velocity);
+
+        for (_, velocity) in emitted(&emitter) {
+            assert!((length(velocity) - 5.0).abs() < 1e-4, "{:?}", velocity);
+            let angle = (velocity[1] / 5.0).min(1.0).acos().to_degrees();
+            assert!(angle <= 20.0 + 1e-2, "{:?} is {} degrees off", velocity, angle);
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use gl;
+use png;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+
+//Reads back the currently bound read framebuffer as RGBA8, rows ordered top to bottom.
+pub fn read_pixels(width: u32, height: u32) -> Vec<u8> {
+    let row_size = width as usize * 4;
+    let mut pixels = vec![0u8; row_size * height as usize];
+
+    unsafe {
+        gl::Finish();
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(
+            0,
+            0,
+            width as i32,
+            height as i32,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut _,
+        );
+    }
+
+    //GL returns the bottom row first
+    let mut flipped = Vec::with_capacity(pixels.len());
+    for row in pixels.chunks(row_size).rev() {
+        flipped.extend_from_slice(row);
+    }
+
+    flipped
+}
+
+pub fn save_png<P: AsRef<Path>>(path: P, width: u32, height: u32, pixels: &[u8]) -> io::Result<()> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header().map_err(io::Error::other)?;
+    writer.write_image_data(pixels).map_err(io::Error::other)
+}
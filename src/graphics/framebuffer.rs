@@ -4,6 +4,7 @@ use std::ops::Drop;
 
 pub struct FrameBuffer {
     gl_handle: u32,
+    depth_stencil: u32,
     color_buffer: Texture,
     highlights: Texture,
 }
@@ -13,6 +14,7 @@ impl FrameBuffer {
     pub fn new(width: u32, height: u32) -> FrameBuffer {
         let mut frame_buffer = FrameBuffer {
             gl_handle: 0,
+            depth_stencil: 0,
             color_buffer: Texture::new(width, height),
             highlights: Texture::new(width, height),
         };
@@ -36,9 +38,8 @@ impl FrameBuffer {
                 0,
             );
 
-            let mut render_buffer_obj = 0u32;
-            gl::GenRenderbuffers(1, &mut render_buffer_obj);
-            gl::BindRenderbuffer(gl::RENDERBUFFER, render_buffer_obj);
+            gl::GenRenderbuffers(1, &mut frame_buffer.depth_stencil);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, frame_buffer.depth_stencil);
             gl::RenderbufferStorage(
                 gl::RENDERBUFFER,
                 gl::DEPTH24_STENCIL8,
@@ -51,7 +52,7 @@ impl FrameBuffer {
                 gl::FRAMEBUFFER,
                 gl::DEPTH_STENCIL_ATTACHMENT,
                 gl::RENDERBUFFER,
-                render_buffer_obj,
+                frame_buffer.depth_stencil,
             );
 
             if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
@@ -64,6 +65,14 @@ impl FrameBuffer {
         frame_buffer
     }
 
+    pub fn width(&self) -> u32 {
+        self.color_buffer.width()
+    }
+
+    pub fn height(&self) -> u32 {
+        self.color_buffer.height()
+    }
+
     pub fn bind(&mut self) {
         unsafe {
             gl::BindFramebuffer(gl::FRAMEBUFFER, self.gl_handle);
@@ -89,7 +98,8 @@ impl FrameBuffer {
 impl Drop for FrameBuffer {
     fn drop(&mut self) {
         unsafe {
-            gl::DeleteFramebuffers(1, &mut self.gl_handle);
+            gl::DeleteFramebuffers(1, &self.gl_handle);
+            gl::DeleteRenderbuffers(1, &self.depth_stencil);
         }
     }
 }
@@ -2,3 +2,6 @@ pub mod shader;
 pub mod vao;
 pub mod framebuffer;
 pub mod texture;
+pub mod capture;
+pub mod preprocessor;
+pub mod program_set;
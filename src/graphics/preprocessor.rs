@@ -0,0 +1,276 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+//Result of expanding a shader file. files[i] is the file behind GLSL source string
+//number i, which is what the #line directives in source and the driver logs refer to.
+#[derive(Debug)]
+pub struct PreprocessedSource {
+    pub source: String,
+    pub files: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum PreprocessError {
+    Read {
+        path: String,
+        error: io::Error,
+    },
+    Syntax {
+        path: String,
+        line: u32,
+        message: String,
+    },
+}
+
+
+//Expands `#include "file"` (relative to the including file, every file is included at
+//most once, however its path is spelled), injects `#define name value` right after `#version` and emits #line
+//directives so compile errors point at the original file and line.
+pub fn preprocess(path: &str, defines: &[(String, String)]) -> Result<PreprocessedSource, PreprocessError> {
+    let mut result = PreprocessedSource {
+        source: String::new(),
+        files: Vec::new(),
+    };
+
+    let path = normalize_path(Path::new(path)).to_string_lossy().into_owned();
+    let contents = read_file(&path)?;
+    let mut lines = contents.lines().enumerate().peekable();
+    result.files.push(path.clone());
+
+    //#version has to stay the very first statement
+    if let Some(&(_, line)) = lines.peek() {
+        if line.trim_start().starts_with("#version") {
+            result.source.push_str(line);
+            result.source.push('\n');
+            lines.next();
+        }
+    }
+
+    for (name, value) in defines {
+        result.source.push_str(&format!("#define {} {}\n", name, value));
+    }
+
+    let first_line = lines.peek().map(|&(index, _)| index + 1).unwrap_or(1);
+    result.source.push_str(&format!("#line {} 0\n", first_line));
+    expand_lines(&path, 0, lines, &mut result)?;
+
+    Ok(result)
+}
+
+
+fn expand_file(path: &str, result: &mut PreprocessedSource) -> Result<(), PreprocessError> {
+    let contents = read_file(path)?;
+    let source_index = result.files.len();
+    result.files.push(path.to_string());
+
+    result.source.push_str(&format!("#line 1 {}\n", source_index));
+    expand_lines(path, source_index, contents.lines().enumerate().peekable(), result)
+}
+
+
+fn expand_lines<'a, I>(path: &str, source_index: usize, lines: I, result: &mut PreprocessedSource) -> Result<(), PreprocessError>
+where
+    I: Iterator<Item = (usize, &'a str)>,
+{
+    for (index, line) in lines {
+        let line_number = index as u32 + 1;
+        let trimmed = line.trim_start();
+        if !trimmed.starts_with("#include") {
+            result.source.push_str(line);
+            result.source.push('\n');
+            continue;
+        }
+
+        let include = parse_include(&trimmed["#include".len()..]).ok_or_else(|| PreprocessError::Syntax {
+            path: path.to_string(),
+            line: line_number,
+            message: format!("expected #include \"file\", found {}", trimmed),
+        })?;
+        let include_path = normalize_path(&Path::new(path)
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(include))
+            .to_string_lossy()
+            .into_owned();
+
+        if !result.files.contains(&include_path) {
+            expand_file(&include_path, result)?;
+        }
+        result.source.push_str(&format!("#line {} {}\n", line_number + 1, source_index));
+    }
+
+    Ok(())
+}
+
+
+//Resolves . and .. without touching the file system, so "a/../b.glsl" and "b.glsl" are the
+//same file for the include guard.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir) | Some(Component::Prefix(_)) => {}
+                _ => normalized.push(".."),
+            },
+            other => normalized.push(other.as_os_str()),
+        }
+    }
+
+    normalized
+}
+
+
+fn parse_include(rest: &str) -> Option<&str> {
+    let rest = rest.trim();
+    if rest.len() > 2 && rest.starts_with('"') && rest.ends_with('"') {
+        Some(&rest[1..rest.len() - 1])
+    } else {
+        None
+    }
+}
+
+
+fn read_file(path: &str) -> Result<String, PreprocessError> {
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .map_err(|error| PreprocessError::Read {
+            path: path.to_string(),
+            error,
+        })?;
+
+    Ok(contents)
+}
+
+
+impl fmt::Display for PreprocessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PreprocessError::Read {
+                ref path,
+                ref error,
+            } => write!(f, "can't read {}: {}", path, error),
+            PreprocessError::Syntax {
+                ref path,
+                line,
+                ref message,
+            } => write!(f, "{}:{}: {}", path, line, message),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    //Fresh directory under the system temp dir holding the given files.
+    fn write_files(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("preprocessor-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for &(file, contents) in files {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+
+        dir
+    }
+
+    fn path_in(dir: &Path, file: &str) -> String {
+        dir.join(file).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn expands_includes_with_line_directives() {
+        let dir = write_files("expand", &[
+            ("main.glsl", "#version 430\nvoid a();\n#include \"common.glsl\"\nvoid b();\n"),
+            ("common.glsl", "float c;\nfloat d;\n"),
+        ]);
+        let defines = [("X".to_string(), "1".to_string())];
+
+        let result = preprocess(&path_in(&dir, "main.glsl"), &defines).unwrap();
+
+        assert_eq!(result.source, "#version 430\n#define X 1\n#line 2 0\nvoid a();\n\
+            #line 1 1\nfloat c;\nfloat d;\n#line 4 0\nvoid b();\n");
+        assert_eq!(result.files, vec![path_in(&dir, "main.glsl"), path_in(&dir, "common.glsl")]);
+    }
+
+    #[test]
+    fn nested_includes_are_relative_to_the_including_file() {
+        let dir = write_files("nested", &[
+            ("main.glsl", "#include \"lib/a.glsl\"\nvoid main();\n"),
+            ("lib/a.glsl", "#include \"b.glsl\"\nfloat a;\n"),
+            ("lib/b.glsl", "float b;\n"),
+        ]);
+
+        let result = preprocess(&path_in(&dir, "main.glsl"), &[]).unwrap();
+
+        assert_eq!(result.source, "#line 1 0\n#line 1 1\n#line 1 2\nfloat b;\n#line 2 1\nfloat a;\n\
+            #line 2 0\nvoid main();\n");
+        assert_eq!(result.files[2], path_in(&dir, "lib/b.glsl"));
+    }
+
+    #[test]
+    fn includes_a_file_once_however_its_path_is_spelled() {
+        let dir = write_files("once", &[
+            ("main.glsl", "#include \"common.glsl\"\n#include \"lib/../common.glsl\"\n#include \"./common.glsl\"\n"),
+            ("common.glsl", "float c;\n"),
+        ]);
+
+        let result = preprocess(&path_in(&dir, "main.glsl"), &[]).unwrap();
+
+        assert_eq!(result.source.matches("float c;").count(), 1);
+        assert_eq!(result.files.len(), 2);
+    }
+
+    #[test]
+    fn include_cycles_stop_at_files_already_included() {
+        let dir = write_files("cycle", &[
+            ("a.glsl", "#include \"b.glsl\"\nfloat a;\n"),
+            ("b.glsl", "#include \"a.glsl\"\nfloat b;\n"),
+        ]);
+
+        let result = preprocess(&path_in(&dir, "a.glsl"), &[]).unwrap();
+
+        assert_eq!(result.source, "#line 1 0\n#line 1 1\n#line 2 1\nfloat b;\n#line 2 0\nfloat a;\n");
+        assert_eq!(result.files, vec![path_in(&dir, "a.glsl"), path_in(&dir, "b.glsl")]);
+    }
+
+    #[test]
+    fn reports_malformed_includes_with_their_line() {
+        let dir = write_files("syntax", &[("main.glsl", "float a;\n#include common.glsl\n")]);
+
+        match preprocess(&path_in(&dir, "main.glsl"), &[]) {
+            Err(PreprocessError::Syntax { line, .. }) => assert_eq!(line, 2),
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reports_missing_includes() {
+        let dir = write_files("missing", &[("main.glsl", "#include \"nope.glsl\"\n")]);
+
+        match preprocess(&path_in(&dir, "main.glsl"), &[]) {
+            Err(PreprocessError::Read { path, .. }) => assert_eq!(path, path_in(&dir, "nope.glsl")),
+            other => panic!("expected a read error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn normalizes_dot_components() {
+        assert_eq!(normalize_path(Path::new("shaders/lib/../common.glsl")), Path::new("shaders/common.glsl"));
+        assert_eq!(normalize_path(Path::new("./shaders/./a.glsl")), Path::new("shaders/a.glsl"));
+        assert_eq!(normalize_path(Path::new("../a.glsl")), Path::new("../a.glsl"));
+        assert_eq!(normalize_path(Path::new("/../a.glsl")), Path::new("/a.glsl"));
+    }
+}
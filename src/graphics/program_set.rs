@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+use graphics::shader;
+use graphics::shader::ShaderError;
+use graphics::shader::ShaderInputData;
+use graphics::shader::ShaderProgram;
+
+//Names one of the programs in a ProgramSet and knows what it is built from.
+pub trait ProgramSource: Copy + PartialEq {
+    fn inputs(&self) -> Vec<ShaderInputData>;
+}
+
+//reload_changed checks the files at most this often, it is called every frame.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+//A group of shader programs that are rebuilt when their source files change.
+pub struct ProgramSet<P> {
+    programs: Vec<(P, ShaderProgram)>,
+    //Last seen modification time of every shader source, see reload_changed.
+    shader_mtimes: HashMap<String, Option<SystemTime>>,
+    last_poll: Option<Instant>
+}
+
+
+impl<P: ProgramSource> ProgramSet<P> {
+    //The programs are empty until load_all is called.
+    pub fn new(programs: &[P]) -> ProgramSet<P> {
+        ProgramSet {
+            programs: programs.iter().map(|&program| (program, ShaderProgram::new())).collect(),
+            shader_mtimes: HashMap::new(),
+            last_poll: None
+        }
+    }
+
+    pub fn get(&self, program: P) -> &ShaderProgram {
+        &self.programs.iter()
+            .find(|&&(p, _)| p == program)
+            .expect("program is not part of the set")
+            .1
+    }
+
+    //Rebuilds every program. Programs that fail to build keep their previous
+    //version, so a typo while editing a shader doesn't leave a broken program bound.
+    pub fn load_all(&mut self) -> Result<(), Vec<ShaderError>> {
+        let programs: Vec<P> = self.programs.iter().map(|&(program, _)| program).collect();
+        self.reload(&programs)
+    }
+
+    //Rebuilds only the programs whose source files were modified since they were last
+    //loaded. Returns None when nothing changed or the files were checked less than
+    //POLL_INTERVAL ago.
+    pub fn reload_changed(&mut self) -> Option<Result<(), Vec<ShaderError>>> {
+        let now = Instant::now();
+        if self.last_poll.is_some_and(|last_poll| now.duration_since(last_poll) < POLL_INTERVAL) {
+            return None;
+        }
+        self.last_poll = Some(now);
+
+        let mut changed = Vec::new();
+        for (path, mtime) in self.shader_mtimes.iter_mut() {
+            let current = modified_time(path);
+            if current != *mtime {
+                *mtime = current;
+                changed.push(path.clone());
+            }
+        }
+
+        if changed.is_empty() {
+            return None;
+        }
+
+        let programs: Vec<P> = self.programs.iter()
+            .map(|&(program, _)| program)
+            .filter(|&program| self.source_files(program).iter().any(|file| changed.contains(file)))
+            .collect();
+
+        Some(self.reload(&programs))
+    }
+
+    fn reload(&mut self, programs: &[P]) -> Result<(), Vec<ShaderError>> {
+        for &program in programs {
+            for file in self.source_files(program) {
+                let mtime = modified_time(&file);
+                self.shader_mtimes.insert(file, mtime);
+            }
+        }
+
+        //Compile everything before touching the current programs.
+        let results: Vec<_> = programs.iter()
+            .map(|&program| (program, shader::create_shader_from(&program.inputs())))
+            .collect();
+
+        let mut errors = Vec::new();
+        for (program, result) in results {
+            match result {
+                Ok(new_program) => *self.get_mut(program) = new_program,
+                Err(err) => errors.push(err)
+            }
+        }
+
+        //Start watching files that only showed up through a new #include.
+        for &program in programs {
+            for file in self.source_files(program) {
+                let mtime = modified_time(&file);
+                self.shader_mtimes.entry(file).or_insert(mtime);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    fn get_mut(&mut self, program: P) -> &mut ShaderProgram {
+        &mut self.programs.iter_mut()
+            .find(|&&mut (p, _)| p == program)
+            .expect("program is not part of the set")
+            .1
+    }
+
+    //Files the program is built from, including the ones its current version was built with.
+    fn source_files(&self, program: P) -> Vec<String> {
+        let mut files: Vec<String> = program.inputs().iter().map(|input| input.source_file().to_string()).collect();
+        for file in self.get(program).source_files() {
+            if !files.contains(file) {
+                files.push(file.clone());
+            }
+        }
+
+        files
+    }
+}
+
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
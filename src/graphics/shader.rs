@@ -1,13 +1,16 @@
 use gl;
+use graphics::preprocessor;
+use graphics::preprocessor::PreprocessError;
 use std;
 use std::ffi::CString;
-use std::fs::File;
-use std::io::Read;
+use std::fmt;
+use std::io;
 use std::ops::Drop;
 
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone)]
 pub enum ShaderType {
+    #[default]
     Vertex,
     Fragment,
     Geometry,
@@ -20,12 +23,54 @@ pub struct Shader {
     source_file: String,
     gl_handle: u32,
     shader_type: ShaderType,
+    defines: Vec<(String, String)>,
+    //source_file followed by everything it included, filled in by compile
+    files: Vec<String>,
 }
 
 
 #[derive(Debug, Default)]
 pub struct ShaderProgram {
     gl_handle: u32,
+    source_files: Vec<String>,
+}
+
+
+//One line of the driver info log. source and line are filled in when the driver
+//prefixed the message with a location (e.g. Mesa "0:12(3): error: ...",
+//NVIDIA "0(12) : error ..." or AMD "ERROR: 0:12: ..."), file is the shader or
+//included file that source string number refers to.
+#[derive(Debug, Clone)]
+pub struct LogLine {
+    pub source: Option<u32>,
+    pub line: Option<u32>,
+    pub file: Option<String>,
+    pub message: String,
+}
+
+
+#[derive(Debug)]
+pub enum ShaderError {
+    Read {
+        stage: ShaderType,
+        path: String,
+        error: io::Error,
+    },
+    Preprocess {
+        stage: ShaderType,
+        path: String,
+        line: u32,
+        message: String,
+    },
+    Compile {
+        stage: ShaderType,
+        path: String,
+        log: Vec<LogLine>,
+    },
+    Link {
+        paths: Vec<String>,
+        log: Vec<LogLine>,
+    },
 }
 
 
@@ -33,6 +78,7 @@ impl ShaderProgram {
     pub fn new() -> ShaderProgram {
         ShaderProgram {
             gl_handle: unsafe { gl::CreateProgram() },
+            source_files: Vec::new(),
         }
     }
 
@@ -47,27 +93,54 @@ impl ShaderProgram {
             );
             gl::AttachShader(self.gl_handle, shader.gl_handle);
         }
+        for file in &shader.files {
+            if !self.source_files.contains(file) {
+                self.source_files.push(file.clone());
+            }
+        }
+    }
+
+    pub fn source_files(&self) -> &[String] {
+        &self.source_files
     }
 
-    pub fn link(&self) {
+    pub fn link(&self) -> Result<(), ShaderError> {
         unsafe {
             gl::LinkProgram(self.gl_handle);
 
             let mut success = gl::FALSE as gl::types::GLint;
             gl::GetProgramiv(self.gl_handle, gl::LINK_STATUS, &mut success);
             if success != gl::TRUE as gl::types::GLint {
-                let info_log = String::with_capacity(256);
-                let mut error_size = 0i32;
-                gl::GetShaderInfoLog(self.gl_handle, 512, &mut error_size, info_log.as_ptr() as _);
-                println!(
-                    "Error link failed with error: {:?} for: {:?}",
-                    info_log,
-                    self.gl_handle
+                let mut log_size = 0i32;
+                gl::GetProgramiv(self.gl_handle, gl::INFO_LOG_LENGTH, &mut log_size);
+                let mut info_log = vec![0u8; log_size.max(1) as usize];
+                gl::GetProgramInfoLog(
+                    self.gl_handle,
+                    info_log.len() as i32,
+                    &mut log_size,
+                    info_log.as_mut_ptr() as *mut _,
                 );
-            } else {
-                println!("Linked successfully {}", self.gl_handle);
+                info_log.truncate(log_size as usize);
+
+                return Err(ShaderError::Link {
+                    paths: self.source_files.clone(),
+                    log: parse_info_log(&info_log, &[]),
+                });
             }
         }
+
+        println!("Linked successfully {}", self.gl_handle);
+        Ok(())
+    }
+
+    //local_size_x/y/z of a linked compute program.
+    pub fn compute_work_group_size(&self) -> [u32; 3] {
+        let mut size = [0i32; 3];
+        unsafe {
+            gl::GetProgramiv(self.gl_handle, gl::COMPUTE_WORK_GROUP_SIZE, size.as_mut_ptr());
+        }
+
+        [size[0] as u32, size[1] as u32, size[2] as u32]
     }
 
     pub fn bind(&self) {
@@ -96,6 +169,34 @@ impl ShaderProgram {
         }
     }
 
+    pub fn set_uniform_2f(&self, name: &str, values: &[f32; 2]) {
+        let location = self.get_uniform_location(name);
+        unsafe {
+            gl::Uniform2f(location, values[0], values[1]);
+        }
+    }
+
+    pub fn set_uniform_2i(&self, name: &str, values: &[i32; 2]) {
+        let location = self.get_uniform_location(name);
+        unsafe {
+            gl::Uniform2i(location, values[0], values[1]);
+        }
+    }
+
+    pub fn set_uniform_3i(&self, name: &str, values: &[i32; 3]) {
+        let location = self.get_uniform_location(name);
+        unsafe {
+            gl::Uniform3i(location, values[0], values[1], values[2]);
+        }
+    }
+
+    pub fn set_uniform_3f(&self, name: &str, values: &[f32; 3]) {
+        let location = self.get_uniform_location(name);
+        unsafe {
+            gl::Uniform3f(location, values[0], values[1], values[2]);
+        }
+    }
+
     pub fn set_uniform_3fv(&self, name: &str, count: i32, values: &[f32]) {
         let location = self.get_uniform_location(name);
         unsafe {
@@ -103,6 +204,13 @@ impl ShaderProgram {
         }
     }
 
+    pub fn set_uniform_4fv(&self, name: &str, count: i32, values: &[f32]) {
+        let location = self.get_uniform_location(name);
+        unsafe {
+            gl::Uniform4fv(location, count, values.as_ptr() as *const _);
+        }
+    }
+
     pub fn set_uniform_1fv(&self, name: &str, count: i32, values: &[f32]) {
         let location = self.get_uniform_location(name);
         unsafe {
@@ -117,6 +225,13 @@ impl ShaderProgram {
         }
     }
 
+    pub fn set_uniform_1ui(&self, name: &str, value: u32) {
+        let location = self.get_uniform_location(name);
+        unsafe {
+            gl::Uniform1ui(location, value);
+        }
+    }
+
     pub fn set_uniform_1f(&self, name: &str, value: f32) {
         let location = self.get_uniform_location(name);
         unsafe {
@@ -138,11 +253,30 @@ impl Shader {
         Shader {
             source_file: source_file.to_string(),
             gl_handle: 0,
-            shader_type: shader_type,
+            shader_type,
+            defines: Vec::new(),
+            files: Vec::new(),
         }
     }
 
-    pub fn compile(&mut self) {
+    //Injected as #define name value right after the #version line.
+    pub fn define(&mut self, name: &str, value: &str) {
+        self.defines.push((name.to_string(), value.to_string()));
+    }
+
+    pub fn compile(&mut self) -> Result<(), ShaderError> {
+        let stage = self.shader_type;
+        let preprocessed = preprocessor::preprocess(&self.source_file, &self.defines).map_err(|err| match err {
+            PreprocessError::Read { path, error } => ShaderError::Read { stage, path, error },
+            PreprocessError::Syntax { path, line, message } => ShaderError::Preprocess {
+                stage,
+                path,
+                line,
+                message,
+            },
+        })?;
+        self.files = preprocessed.files;
+
         unsafe {
             let shader_type = get_gl_shader_type(&self.shader_type);
             println!(
@@ -153,9 +287,11 @@ impl Shader {
             );
             self.gl_handle = gl::CreateShader(shader_type);
 
-            let file_buf = self.read_shader_file();
-
-            let shader_str = CString::new(file_buf).unwrap();
+            let shader_str = CString::new(preprocessed.source).map_err(|_| ShaderError::Read {
+                stage: self.shader_type,
+                path: self.source_file.clone(),
+                error: io::Error::new(io::ErrorKind::InvalidData, "shader source contains a nul byte"),
+            })?;
             gl::ShaderSource(self.gl_handle, 1, &shader_str.as_ptr(), std::ptr::null());
 
             gl::CompileShader(self.gl_handle);
@@ -163,52 +299,178 @@ impl Shader {
             let mut success = gl::FALSE as gl::types::GLint;
             gl::GetShaderiv(self.gl_handle, gl::COMPILE_STATUS, &mut success);
             if success != gl::TRUE as gl::types::GLint {
-                let info_log = String::with_capacity(256);
-                let mut error_size = 0i32;
-                gl::GetShaderInfoLog(self.gl_handle, 512, &mut error_size, info_log.as_ptr() as _);
-                println!(
-                    "Error compile failed with error: {:?} for: {:?}",
-                    info_log,
-                    self.gl_handle
+                let mut log_size = 0i32;
+                gl::GetShaderiv(self.gl_handle, gl::INFO_LOG_LENGTH, &mut log_size);
+                let mut info_log = vec![0u8; log_size.max(1) as usize];
+                gl::GetShaderInfoLog(
+                    self.gl_handle,
+                    info_log.len() as i32,
+                    &mut log_size,
+                    info_log.as_mut_ptr() as *mut _,
                 );
+                info_log.truncate(log_size as usize);
+
+                return Err(ShaderError::Compile {
+                    stage: self.shader_type,
+                    path: self.source_file.clone(),
+                    log: parse_info_log(&info_log, &self.files),
+                });
             }
         }
-    }
 
-    fn read_shader_file(&self) -> Vec<u8> {
-        let mut file = File::open(&self.source_file).expect("ERROR: Shader file not found!");
-
-        let mut file_buf = Vec::new();
-        file.read_to_end(&mut file_buf).unwrap();
-
-        file_buf
+        Ok(())
     }
 }
 
 pub struct ShaderInputData {
     shader_type: ShaderType,
     shader_source_file: String,
+    defines: Vec<(String, String)>,
 }
 
 impl ShaderInputData {
     pub fn new(shader_type: ShaderType, shader_source_file: &str) -> ShaderInputData {
         ShaderInputData {
-            shader_type: shader_type,
+            shader_type,
             shader_source_file: shader_source_file.to_string(),
+            defines: Vec::new(),
         }
     }
+
+    pub fn define<T: ToString>(mut self, name: &str, value: T) -> ShaderInputData {
+        self.defines.push((name.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn source_file(&self) -> &str {
+        &self.shader_source_file
+    }
 }
 
-pub fn create_shader_from(input: &[ShaderInputData]) -> ShaderProgram {
+pub fn create_shader_from(input: &[ShaderInputData]) -> Result<ShaderProgram, ShaderError> {
     let mut result = ShaderProgram::new();
     for field in input {
         let mut shader = Shader::new(field.shader_type, &field.shader_source_file);
-        shader.compile();
+        for (name, value) in &field.defines {
+            shader.define(name, value);
+        }
+        shader.compile()?;
         result.attach_shader(&shader);
     }
 
-    result.link();
-    result
+    result.link()?;
+    Ok(result)
+}
+
+
+impl LogLine {
+    fn parse(text: &str) -> LogLine {
+        let text = text.trim();
+        let mut severity = "";
+        let mut rest = text;
+        for prefix in &["ERROR: ", "WARNING: "] {
+            if rest.starts_with(prefix) {
+                severity = prefix;
+                rest = &rest[prefix.len()..];
+            }
+        }
+
+        match parse_location(rest) {
+            Some((source, line, message)) => LogLine {
+                source: Some(source),
+                line: Some(line),
+                file: None,
+                message: format!("{}{}", severity.to_lowercase(), message),
+            },
+            None => LogLine {
+                source: None,
+                line: None,
+                file: None,
+                message: text.to_string(),
+            },
+        }
+    }
+}
+
+
+impl fmt::Display for LogLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+
+impl fmt::Display for ShaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ShaderError::Read {
+                stage,
+                ref path,
+                ref error,
+            } => write!(f, "Can't read {:?} shader {}: {}", stage, path, error),
+            ShaderError::Preprocess {
+                stage,
+                ref path,
+                line,
+                ref message,
+            } => write!(f, "{:?} shader {}:{}: {}", stage, path, line, message),
+            ShaderError::Compile {
+                stage,
+                ref path,
+                ref log,
+            } => {
+                write!(f, "{:?} shader {} failed to compile:", stage, path)?;
+                for line in log {
+                    match line.line {
+                        Some(number) => {
+                            let file = line.file.as_ref().unwrap_or(path);
+                            write!(f, "\n  {}:{}: {}", file, number, line.message)?
+                        }
+                        None => write!(f, "\n  {}", line.message)?,
+                    }
+                }
+                Ok(())
+            }
+            ShaderError::Link { ref paths, ref log } => {
+                write!(f, "Program ({}) failed to link:", paths.join(", "))?;
+                for line in log {
+                    write!(f, "\n  {}", line)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+
+impl std::error::Error for ShaderError {}
+
+
+//The #line directives from the preprocessor make the source number of a line the index into
+//files. Mesa always reports source 0, so there the line is right but the file shown for errors
+//inside includes is the including one.
+fn parse_info_log(info_log: &[u8], files: &[String]) -> Vec<LogLine> {
+    String::from_utf8_lossy(info_log)
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|text| {
+            let mut line = LogLine::parse(text);
+            line.file = line.source.and_then(|source| files.get(source as usize).cloned());
+            line
+        })
+        .collect()
+}
+
+
+impl Drop for ShaderProgram {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteProgram(self.gl_handle);
+        }
+    }
 }
 
 
@@ -231,8 +493,98 @@ fn get_gl_shader_type(shader_type: &ShaderType) -> gl::types::GLenum {
 }
 
 
-impl Default for ShaderType {
-    fn default() -> Self {
-        ShaderType::Vertex
+//Parses the "source:line" or "source(line)" location drivers put in front of messages,
+//returns it together with the message that follows (minus the optional column).
+fn parse_location(text: &str) -> Option<(u32, u32, &str)> {
+    let source_end = text.find(|c: char| !c.is_ascii_digit())?;
+    let source = text[..source_end].parse().ok()?;
+    let rest = &text[source_end..];
+    let rest = if rest.starts_with(':') || rest.starts_with('(') {
+        &rest[1..]
+    } else {
+        return None;
+    };
+
+    let line_end = rest.find(|c: char| !c.is_ascii_digit())?;
+    let line = rest[..line_end].parse().ok()?;
+    let mut rest = &rest[line_end..];
+
+    //Mesa adds the column as "(3)", NVIDIA closes the line with ")"
+    if rest.starts_with('(') {
+        if let Some(end) = rest.find(')') {
+            rest = &rest[end + 1..];
+        }
+    }
+    let message = rest.trim_start_matches(|c: char| c == ')' || c == ':' || c.is_whitespace());
+
+    Some((source, line, message))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_mesa_locations() {
+        let line = LogLine::parse("0:12(3): error: `foo' undeclared");
+        assert_eq!(line.source, Some(0));
+        assert_eq!(line.line, Some(12));
+        assert_eq!(line.message, "error: `foo' undeclared");
+    }
+
+    #[test]
+    fn parses_nvidia_locations() {
+        let line = LogLine::parse("1(27) : error C1008: undefined variable \"foo\"");
+        assert_eq!(line.source, Some(1));
+        assert_eq!(line.line, Some(27));
+        assert_eq!(line.message, "error C1008: undefined variable \"foo\"");
+    }
+
+    #[test]
+    fn parses_amd_severity_prefixes() {
+        let line = LogLine::parse("ERROR: 2:5: 'foo' : undeclared identifier");
+        assert_eq!(line.source, Some(2));
+        assert_eq!(line.line, Some(5));
+        assert_eq!(line.message, "error: 'foo' : undeclared identifier");
+
+        let line = LogLine::parse("WARNING: 0:8: unused variable");
+        assert_eq!(line.line, Some(8));
+        assert_eq!(line.message, "warning: unused variable");
+    }
+
+    #[test]
+    fn keeps_lines_without_a_location() {
+        for text in &["error: linking failed", "ERROR: 1 compilation errors.  No code generated.", "0x12: odd", "12"] {
+            let line = LogLine::parse(text);
+            assert_eq!(line.source, None, "{}", text);
+            assert_eq!(line.line, None, "{}", text);
+            assert_eq!(line.message, *text);
+        }
+    }
+
+    #[test]
+    fn parse_location_splits_off_the_column() {
+        assert_eq!(parse_location("3:4(10): message"), Some((3, 4, "message")));
+        assert_eq!(parse_location("3(4) : message"), Some((3, 4, "message")));
+        assert_eq!(parse_location("3:4: message"), Some((3, 4, "message")));
+        assert_eq!(parse_location("a:4: message"), None);
+        assert_eq!(parse_location("3 4 message"), None);
+    }
+
+    #[test]
+    fn maps_sources_back_to_files() {
+        let files = vec!["shaders/main.glsl".to_string(), "shaders/common.glsl".to_string()];
+        let log = parse_info_log(b"0:3(1): error: a\n\n1(7) : error b\n5:1: error: c\nlink failed\n", &files);
+
+        assert_eq!(log.len(), 4);
+        assert_eq!(log[0].file.as_deref(), Some("shaders/main.glsl"));
+        assert_eq!(log[1].file.as_deref(), Some("shaders/common.glsl"));
+        assert_eq!(log[1].line, Some(7));
+        //Source numbers the preprocessor never emitted keep the line but have no file.
+        assert_eq!(log[2].file, None);
+        assert_eq!(log[2].line, Some(1));
+        assert_eq!(log[3].file, None);
+        assert_eq!(log[3].message, "link failed");
     }
 }
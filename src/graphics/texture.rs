@@ -1,5 +1,6 @@
 use gl;
 use std;
+use std::ops::Drop;
 
 pub struct Texture {
     pub gl_handle: u32,
@@ -12,8 +13,8 @@ impl Texture {
     pub fn new(width: u32, height: u32) -> Texture {
         let mut texture = Texture {
             gl_handle: 0,
-            width: width,
-            height: height,
+            width,
+            height,
         };
 
         unsafe {
@@ -41,6 +42,14 @@ impl Texture {
         texture
     }
 
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
     pub fn bind(&mut self) {
         unsafe {
             gl::BindTexture(gl::TEXTURE_2D, self.gl_handle);
@@ -53,3 +62,12 @@ impl Texture {
         }
     }
 }
+
+
+impl Drop for Texture {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.gl_handle);
+        }
+    }
+}
@@ -26,11 +26,36 @@ impl VertexBufferObj {
         vbo
     }
 
-    pub fn set_buffer_data_from_raw_ptr(&mut self, data: *const std::os::raw::c_void, size: isize) {
+    pub fn bind(&self) {
         unsafe {
             gl::BindBuffer(gl::ARRAY_BUFFER, self.gl_handle);
+        }
+    }
+
+    /// # Safety
+    /// `data` has to point to at least `size` readable bytes.
+    pub unsafe fn set_buffer_data_from_raw_ptr(&mut self, data: *const std::os::raw::c_void, size: isize) {
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.gl_handle);
+
+        gl::BufferData(gl::ARRAY_BUFFER, size, data, gl::STATIC_DRAW);
+    }
+
+    /// # Safety
+    /// `data` has to point to at least `size` readable bytes and `offset + size` must fit in the buffer.
+    pub unsafe fn set_buffer_sub_data_from_raw_ptr(&mut self, offset: isize, data: *const std::os::raw::c_void, size: isize) {
+        gl::BindBuffer(gl::ARRAY_BUFFER, self.gl_handle);
 
-            gl::BufferData(gl::ARRAY_BUFFER, size as isize, data, gl::STATIC_DRAW);
+        gl::BufferSubData(gl::ARRAY_BUFFER, offset, size, data);
+    }
+
+    //Copies the first size bytes of source to the start of this buffer, on the GPU.
+    pub fn copy_from(&mut self, source: &VertexBufferObj, size: isize) {
+        unsafe {
+            gl::BindBuffer(gl::COPY_READ_BUFFER, source.gl_handle);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.gl_handle);
+            gl::CopyBufferSubData(gl::COPY_READ_BUFFER, gl::COPY_WRITE_BUFFER, 0, 0, size);
+            gl::BindBuffer(gl::COPY_READ_BUFFER, 0);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
         }
     }
 
@@ -40,7 +65,7 @@ impl VertexBufferObj {
 
             gl::BufferData(
                 gl::ARRAY_BUFFER,
-                (std::mem::size_of::<f32>() * data.len()) as isize,
+                std::mem::size_of_val(data) as isize,
                 data.as_ptr() as *const _,
                 gl::STATIC_DRAW,
             );
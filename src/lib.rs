@@ -0,0 +1,43 @@
+extern crate cgmath;
+extern crate gl;
+extern crate gltf;
+extern crate png;
+extern crate rand;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate tobj;
+extern crate toml;
+
+pub mod particle_system;
+pub mod graphics;
+pub mod camera;
+pub mod scene;
+pub mod emitter;
+pub mod renderer;
+pub mod collider;
+pub mod mesh;
+pub mod distance_grid;
+pub mod terrain;
+pub mod bounds;
+pub mod demo;
+
+pub use particle_system::ParticleSystem;
+pub use camera::Camera;
+pub use scene::Scene;
+pub use emitter::Emitter;
+pub use renderer::Renderer;
+pub use collider::Collider;
+pub use terrain::Terrain;
+
+
+trait Miliseconds {
+    fn as_milis(&self) -> u64;
+}
+
+
+impl Miliseconds for std::time::Duration {
+    fn as_milis(&self) -> u64 {
+        (self.as_secs() as f64 * 1000.0 + self.subsec_nanos() as f64 * 1e-6) as u64
+    }
+}
@@ -1,36 +1,24 @@
-extern crate cgmath;
 extern crate gl;
-extern crate rand;
+extern crate rust_particles;
 extern crate sdl2;
 
-mod particle_system;
-mod graphics;
-mod camera;
+use rust_particles::{Camera, ParticleSystem};
+use rust_particles::demo;
+use rust_particles::demo::Options;
+use rust_particles::graphics::shader::ShaderError;
 
-use graphics::shader;
-use particle_system::ParticleSystem;
-
-use sdl2::event::Event;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
 
 use std::time::Instant;
 use std::os::raw::c_void;
 use std::os::raw::c_char;
-use camera::Camera;
 use sdl2::keyboard::Scancode;
 
+const WINDOW_TITLE: &str = "Rust SDL window";
 
-fn render(particle_system: &mut ParticleSystem, cam: &Camera) {
-    particle_system.render(cam);
-
-    unsafe {
-        gl::Flush();
-    }
-}
 
-
-#[no_mangle]
-pub extern "system" fn debug_callback(
+extern "system" fn debug_callback(
     _: gl::types::GLenum,
     err_type: gl::types::GLenum,
     id: gl::types::GLuint,
@@ -79,7 +67,38 @@ fn handle_input(
 }
 
 
+//Failed programs keep running their previous version, the errors go to the console
+//and the window title says something is wrong until a reload succeeds.
+fn report_shader_reload(result: Result<(), Vec<ShaderError>>, window: &mut sdl2::video::Window) {
+    let title = match result {
+        Ok(()) => WINDOW_TITLE.to_string(),
+        Err(errors) => {
+            for err in &errors {
+                println!("{}", err);
+            }
+            format!("{} - {} shader error(s), old programs kept, see console", WINDOW_TITLE, errors.len())
+        }
+    };
+
+    window.set_title(&title).unwrap();
+}
+
+
 fn main() {
+    let options = Options::parse(std::env::args().skip(1)).unwrap_or_else(|err| {
+        println!("{}\n{}", err, demo::USAGE);
+        std::process::exit(1);
+    });
+    let scene = options.load_scene().unwrap_or_else(|err| {
+        println!("Can't load {}: {}", options.scene_path.as_deref().unwrap_or("the scene"), err);
+        std::process::exit(1);
+    });
+
+    if options.headless && std::env::var_os("SDL_VIDEODRIVER").is_none() {
+        //EGL backed driver that doesn't need a display, works with Mesa llvmpipe.
+        std::env::set_var("SDL_VIDEODRIVER", "offscreen");
+    }
+
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let gl_attr = video_subsystem.gl_attr();
@@ -92,12 +111,15 @@ fn main() {
 
     gl_attr.set_context_version(4, 3);
 
-    let window = video_subsystem
-        .window("Rust SDL window", 1600, 900)
-        .position_centered()
-        .opengl()
-        .build()
-        .unwrap();
+    let (width, height) = options.resolution;
+    let mut window_builder = video_subsystem.window(WINDOW_TITLE, width, height);
+    window_builder.opengl();
+    if options.headless {
+        window_builder.hidden();
+    } else {
+        window_builder.position_centered().resizable();
+    }
+    let mut window = window_builder.build().unwrap();
 
     let _gl_context = window.gl_create_context();
     println!("Started with GL version: {:?}", gl_attr.context_version());
@@ -110,15 +132,35 @@ fn main() {
         gl::DebugMessageCallback(debug_callback, std::ptr::null())
     };
 
+    unsafe { println!("OpenGL version is {:?}", gl::GetString(gl::VERSION)) };
+    //The drawable can be larger than the window on high DPI displays.
+    let (width, height) = window.drawable_size();
+    cam.set_viewport_size(width, height);
+    let mut particle_systems = ParticleSystem::all_from_scene(&scene);
+    println!("Using seed {}", particle_systems[0].seed());
+    let mut renderer = demo::init_graphics(&mut particle_systems, width, height).unwrap_or_else(|err| {
+        println!("{}", err);
+        std::process::exit(1);
+    });
+
+    renderer.set_terrain(scene.terrain.as_ref().filter(|terrain| terrain.mesh));
+
+    if options.headless {
+        if let Err(err) = demo::run_headless(&mut renderer, &mut particle_systems, &cam, width, height, options.frames, &options.out_dir) {
+            println!("Headless run failed: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     video_subsystem.gl_set_swap_interval(1);
 
     let mut event_pump = sdl_context.event_pump().unwrap();
 
-    unsafe { println!("OpenGL version is {:?}", gl::GetString(gl::VERSION)) };
-    let mut particle_system = ParticleSystem::new(1024 * 1024 * 8);
-    particle_system.init_graphics_resources([128, 128, 1]);
-
     let mut prev_time = Instant::now();
+    let mut accumulator = 0.0;
+    //Last update error, printed once instead of every frame until the simulation runs again.
+    let mut update_error = None;
 
     'running: loop {
         let mouse_state = event_pump.mouse_state();
@@ -131,10 +173,22 @@ fn main() {
                     keycode: Some(Keycode::Escape),
                     ..
                 } => break 'running,
-                Event::TextInput { text, .. } => if text == " " {
+                Event::TextInput { ref text, .. } if text == " " => {
                     pause_dt = !pause_dt;
                 },
-                Event::MouseMotion { xrel, yrel, .. } => if mouse_state.left() {
+                Event::KeyDown { keycode: Some(key @ Keycode::PageUp), .. } |
+                Event::KeyDown { keycode: Some(key @ Keycode::PageDown), .. } => {
+                    demo::scale_particle_count(&mut particle_systems, key == Keycode::PageUp);
+                },
+                Event::KeyDown { keycode: Some(Keycode::B), .. } => {
+                    demo::emit_burst(&mut particle_systems[0]);
+                },
+                Event::Window { win_event: WindowEvent::Resized(..), .. } => {
+                    let (width, height) = window.drawable_size();
+                    renderer.resize(width, height);
+                    cam.set_viewport_size(width, height);
+                },
+                Event::MouseMotion { xrel, yrel, .. } if mouse_state.left() => {
                     dx = xrel;
                     dy = yrel;
                 },
@@ -152,33 +206,28 @@ fn main() {
         if keyboard_state.is_scancode_pressed(Scancode::LCtrl)
             && keyboard_state.is_scancode_pressed(Scancode::R)
         {
-            particle_system.load_shaders();
+            report_shader_reload(demo::load_shaders(&mut renderer, &mut particle_systems), &mut window);
+        } else if let Some(result) = demo::reload_changed_shaders(&mut renderer, &mut particle_systems) {
+            report_shader_reload(result, &mut window);
         }
 
         if pause_dt {
             dt_sec = 0.0
         }
 
-        particle_system.update(dt_sec);
+        match demo::step_simulation(&mut particle_systems, &mut accumulator, dt_sec) {
+            Ok(()) => update_error = None,
+            Err(err) => {
+                let message = err.to_string();
+                if update_error.as_ref() != Some(&message) {
+                    println!("Skipping particle update: {}", message);
+                    update_error = Some(message);
+                }
+            }
+        }
 
-        render(&mut particle_system, &cam);
+        demo::render(&mut renderer, &particle_systems, &cam);
         window.gl_swap_window();
     }
 }
 
-
-trait Miliseconds {
-    fn as_milis(&self) -> u64;
-    fn as_secs_f64(&self) -> f64;
-}
-
-
-impl Miliseconds for std::time::Duration {
-    fn as_milis(&self) -> u64 {
-        (self.as_secs() as f64 * 1000.0 + self.subsec_nanos() as f64 * 1e-6) as u64
-    }
-
-    fn as_secs_f64(&self) -> f64 {
-        self.as_secs() as f64 + self.subsec_nanos() as f64 * 1e-9
-    }
-}
@@ -0,0 +1,90 @@
+use cgmath::{Matrix4, Point3, Transform};
+use gltf;
+use std::path::Path;
+use tobj;
+
+pub type Triangle = [[f32; 3]; 3];
+
+//Triangles of every mesh in an OBJ or glTF (.gltf or .glb) file, in the units of the file.
+//glTF meshes are placed by the node hierarchy of the default scene.
+pub fn load_triangles(path: &Path) -> Result<Vec<Triangle>, String> {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("").to_lowercase();
+    match extension.as_str() {
+        "gltf" | "glb" => load_gltf(path),
+        _ => load_obj(path)
+    }
+}
+
+
+//Transforms every vertex by scale, then moves it by position.
+pub fn place(triangles: &mut [Triangle], position: [f32; 3], scale: f32) {
+    for vertex in triangles.iter_mut().flat_map(|triangle| triangle.iter_mut()) {
+        for axis in 0..3 {
+            vertex[axis] = position[axis] + vertex[axis] * scale;
+        }
+    }
+}
+
+
+fn load_obj(path: &Path) -> Result<Vec<Triangle>, String> {
+    let (models, _) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS).map_err(|err| err.to_string())?;
+
+    let mut triangles = Vec::new();
+    for model in models {
+        let mesh = &model.mesh;
+        let vertex = |index: u32| {
+            let i = index as usize * 3;
+            [mesh.positions[i], mesh.positions[i + 1], mesh.positions[i + 2]]
+        };
+        for face in mesh.indices.chunks(3) {
+            triangles.push([vertex(face[0]), vertex(face[1]), vertex(face[2])]);
+        }
+    }
+
+    Ok(triangles)
+}
+
+
+fn load_gltf(path: &Path) -> Result<Vec<Triangle>, String> {
+    let (document, buffers, _) = gltf::import(path).map_err(|err| err.to_string())?;
+    let scene = document.default_scene()
+        .or_else(|| document.scenes().next())
+        .ok_or_else(|| "the file has no scene".to_string())?;
+
+    let mut triangles = Vec::new();
+    for node in scene.nodes() {
+        add_gltf_node(&node, Matrix4::from_scale(1.0), &buffers, &mut triangles);
+    }
+
+    Ok(triangles)
+}
+
+
+fn add_gltf_node(node: &gltf::Node, parent: Matrix4<f32>, buffers: &[gltf::buffer::Data], triangles: &mut Vec<Triangle>) {
+    let transform = parent * Matrix4::from(node.transform().matrix());
+
+    if let Some(mesh) = node.mesh() {
+        for primitive in mesh.primitives().filter(|primitive| primitive.mode() == gltf::mesh::Mode::Triangles) {
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data.0[..]));
+            let positions: Vec<[f32; 3]> = match reader.read_positions() {
+                Some(positions) => positions
+                    .map(|p| transform.transform_point(Point3::new(p[0], p[1], p[2])).into())
+                    .collect(),
+                None => continue
+            };
+            let indices: Vec<u32> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().collect(),
+                None => (0..positions.len() as u32).collect()
+            };
+
+            let valid = |face: &&[u32]| face.len() == 3 && face.iter().all(|&index| (index as usize) < positions.len());
+            for face in indices.chunks(3).filter(valid) {
+                triangles.push([positions[face[0] as usize], positions[face[1] as usize], positions[face[2] as usize]]);
+            }
+        }
+    }
+
+    for child in node.children() {
+        add_gltf_node(&child, transform, buffers, triangles);
+    }
+}
@@ -1,315 +1,1508 @@
 use rand::distributions::{IndependentSample, Range};
 use rand;
+use std::fmt;
+use rand::{Isaac64Rng, Rng, SeedableRng};
 use gl;
 use std;
-use shader;
-use shader::ShaderInputData;
-use shader::ShaderProgram;
-use shader::ShaderType;
-use graphics::framebuffer::FrameBuffer;
+use graphics::program_set::{ProgramSet, ProgramSource};
+use graphics::shader::ShaderError;
+use graphics::shader::ShaderInputData;
+use graphics::shader::ShaderType;
 use super::Miliseconds;
 use camera::Camera;
 use graphics::vao::VertexBufferObj;
 use graphics::vao::VertexArrayObj;
+use scene;
+use scene::Scene;
+use emitter;
+use emitter::{Emitter, VelocityMode};
+use collider;
+use collider::{Collider, ColliderSdf, ColliderTransform, Material, MaterialData};
+use terrain::Terrain;
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
-struct Vec4 {
-    x: f32,
-    y: f32,
-    z: f32,
-    w: f32
+pub struct Vec4 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub w: f32
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
-struct Vec3 {
-    x: f32,
-    y: f32,
-    z: f32
+pub struct Vec3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32
 }
 
+//A burst waiting for the next update, see emit_burst.
+struct Burst {
+    position: [f32; 3],
+    count: u32,
+    velocity: VelocityMode
+}
+
+//Burst as the Bursts buffer in the compute shader expects it (std430).
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone)]
+struct BurstData {
+    position: [f32; 3],
+    //Particles in this and all the previous bursts.
+    end: u32,
+    velocity_a: [f32; 3],
+    velocity_mode: i32,
+    velocity_b: [f32; 3],
+    padding0: f32,
+    velocity_params: [f32; 3],
+    padding1: f32
+}
+
+//Identifies a sphere added with add_sphere or taken from the scene, see spheres.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SphereId(u64);
+
+//Identifies a collider taken from the scene or added with add_collider, see colliders.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ColliderId(u64);
+
+//Sphere and shape colliders, copied to the compute shader's Spheres and Colliders buffers.
+//step moves them to where they are after the next update and works out how fast they went.
 struct ColliderData {
-    sphere_radius: [f32; 20],
-    sphere_positions: [Vec3; 20]
+    //xyz is the center, w the radius.
+    spheres: Vec<Vec4>,
+    //xyz is how fast spheres[i] moved during the last step.
+    sphere_velocities: Vec<Vec4>,
+    //Centers of the spheres at the end of the last step.
+    previous_centers: Vec<[f32; 3]>,
+    //None keeps the classic splash response, see MaterialData::splash.
+    sphere_materials: Vec<Option<Material>>,
+    //sphere_ids[i] names spheres[i].
+    sphere_ids: Vec<SphereId>,
+    next_sphere_id: u64,
+    //Set when spheres changed since the last upload.
+    dirty: bool,
+    //shapes[i] is named by shape_ids[i] and placed by transforms[i], unless it is animated.
+    shapes: Vec<Collider>,
+    shape_ids: Vec<ColliderId>,
+    transforms: Vec<ColliderTransform>,
+    next_collider_id: u64,
+    //The shapes where the scene put them, and where they are after the last step.
+    resting: Vec<ColliderSdf>,
+    moved: Vec<ColliderSdf>,
+    //Samples of the mesh colliders, see Collider::sdf.
+    grids: Vec<f32>,
+    //Set when grids changed since the last upload.
+    grids_dirty: bool,
+    //Seconds simulated so far, drives the collider animations.
+    time: f32
 }
 
+//Number of spheres scattered around when the scene doesn't list any.
+const RANDOM_SPHERES: usize = 20;
+
+//Everything that needs a GL context. Created by init_graphics_resources so the
+//simulation itself can be used (and tested) without a GPU.
+struct GraphicsResources {
+    programs: ProgramSet<Program>,
+    //Ping-pong particle state: update reads the buffers at current and writes the other pair.
+    possition_vbos: [VertexBufferObj; 2],
+    velocity_vbos: [VertexBufferObj; 2],
+    current: usize,
+    //draw_vaos[i] takes the positions from possition_vbos[i] and the previous ones from the other buffer.
+    draw_vaos: [VertexArrayObj; 2],
+    //GL_MAX_COMPUTE_WORK_GROUP_COUNT, see work_groups.
+    max_work_groups: [u32; 3],
+    //Per particle ranks and per group offsets written by Program::SpawnRank.
+    spawn_ranks: VertexBufferObj,
+    spawn_group_offsets: VertexBufferObj,
+    //BurstData of the bursts handed to the current step.
+    bursts: VertexBufferObj,
+    //ColliderData::spheres, sphere_velocities and sphere_materials.
+    spheres: VertexBufferObj,
+    sphere_velocities: VertexBufferObj,
+    sphere_materials: VertexBufferObj,
+    //ColliderData::moved and grids.
+    colliders: VertexBufferObj,
+    collider_grids: VertexBufferObj,
+    //Terrain::heights.
+    terrain_heights: VertexBufferObj,
+    //Triangles of a mesh emitter, see Emitter::mesh_buffer_data.
+    emitter_mesh: VertexBufferObj
+}
+
+#[derive(Debug)]
+pub enum GraphicsError {
+    Shaders(Vec<ShaderError>),
+    //The dispatch needed for this many particles is above GL_MAX_COMPUTE_WORK_GROUP_COUNT.
+    TooManyParticles {
+        count: usize,
+        work_groups: [u64; 3],
+        max_work_groups: [u32; 3]
+    }
+}
+
+//The shader programs owned by GraphicsResources.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Program {
+    Draw,
+    Compute,
+    //Ranks the recycled particles before Compute runs, see shaders/spawn_rank.c.glsl.
+    SpawnRank
+}
+
+//Local work group size of the compute shader, injected as LOCAL_SIZE_X/LOCAL_SIZE_Y.
+const LOCAL_SIZE: [u32; 2] = [32, 32];
+//Particles ranked by one work group of the spawn rank shader, injected as RANK_GROUP_SIZE.
+const RANK_GROUP_SIZE: usize = 1024;
+
 pub struct ParticleSystem {
     particle_pos: Vec<Vec4>,
     particle_vel: Vec<Vec4>,
-    draw_shader_program: ShaderProgram,
-    compute_shader_program: ShaderProgram,
     start: std::time::Instant,
-    possition_vbo: VertexBufferObj,
-    velocity_vbo: VertexBufferObj,
-    draw_vao: VertexArrayObj,
-    screen_vao: VertexArrayObj,
-    compute_shader_work_groups: [u32; 3],
-    frame_buffer: FrameBuffer,
-    blur_frame_buffers: [FrameBuffer; 2],
-    blur_shader: ShaderProgram,
-    screen_program: ShaderProgram,
-    fullscreen_quad_vbo: VertexBufferObj,
-    collider_data: ColliderData
+    collider_data: ColliderData,
+    terrain: Option<Terrain>,
+    seed: u64,
+    seed_offset: [f32; 2],
+    gravity: f32,
+    emitter: Emitter,
+    //Fraction of a particle the emitter rate allowed but that wasn't spawned yet.
+    spawn_carry: f32,
+    bursts: Vec<Burst>,
+    bounds: scene::Bounds,
+    appearance: scene::Appearance,
+    //Used for the particles added by set_particle_count.
+    initial: scene::Volume,
+    rng: Isaac64Rng,
+    //Where between the previous and the latest update the particles are drawn, see set_interpolation.
+    interpolation: f32,
+    graphics: Option<GraphicsResources>
 }
 
 impl ParticleSystem {
     pub fn new(particle_count: usize) -> ParticleSystem {
+        ParticleSystem::from_scene(&Scene {
+            particle_count,
+            ..Scene::default()
+        })
+    }
+
+    //Builds the first system of the scene only, see all_from_scene.
+    //All the randomness (initial particles, random spheres and the respawn hash in the
+    //compute shader) is derived from scene.seed, a random one is picked when it is None.
+    pub fn from_scene(scene: &Scene) -> ParticleSystem {
+        let seed = scene.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        ParticleSystem::from_scene_system(scene, &scene.systems()[0], seed, 0)
+    }
+
+    //One ParticleSystem for every system of the scene, all using the same seed.
+    pub fn all_from_scene(scene: &Scene) -> Vec<ParticleSystem> {
+        let seed = scene.seed.unwrap_or_else(|| rand::thread_rng().gen());
+        scene.systems().iter()
+            .enumerate()
+            .map(|(index, system)| ParticleSystem::from_scene_system(scene, system, seed, index))
+            .collect()
+    }
+
+    fn from_scene_system(scene: &Scene, system: &scene::System, seed: u64, index: usize) -> ParticleSystem {
+        let particle_count = system.particle_count;
+        let mut rng = Isaac64Rng::from_seed(&[seed]);
+
+        //Every system gets the same random spheres, the rest of its randomness depends on its index.
+        let mut collider_data = match scene.spheres {
+            Some(ref spheres) => ColliderData::from_spheres(spheres),
+            None => ColliderData::new(&mut rng)
+        };
+        for collider in &scene.colliders {
+            collider_data.add_collider(collider.clone());
+        }
+        if index > 0 {
+            rng = Isaac64Rng::from_seed(&[seed, index as u64]);
+        }
+        let seed_offset = [rng.gen_range(0.0, 1000.0), rng.gen_range(0.0, 1000.0)];
+
         let mut system = ParticleSystem {
             particle_pos: Vec::with_capacity(particle_count),
             particle_vel: Vec::with_capacity(particle_count),
-            draw_shader_program: ShaderProgram::new(),
-            compute_shader_program: ShaderProgram::new(),
             start: std::time::Instant::now(),
-            possition_vbo: VertexBufferObj::new(),
-            velocity_vbo: VertexBufferObj::new(),
-            draw_vao: VertexArrayObj::new(),
-            screen_vao: VertexArrayObj::new(),
-            compute_shader_work_groups: [0, 0, 0],
-            frame_buffer: FrameBuffer::new(1600, 900),
-            blur_frame_buffers: [FrameBuffer::new(1600, 900), FrameBuffer::new(1600, 900)],
-            screen_program: ShaderProgram::new(),
-            blur_shader: ShaderProgram::new(),
-            fullscreen_quad_vbo: VertexBufferObj::new(),
-            collider_data: ColliderData::new()
+            collider_data,
+            terrain: scene.terrain.clone(),
+            seed,
+            seed_offset,
+            gravity: system.gravity,
+            emitter: system.emitter.clone(),
+            spawn_carry: 0.0,
+            bursts: Vec::new(),
+            bounds: scene.bounds,
+            appearance: system.appearance.clone(),
+            initial: system.initial,
+            rng,
+            interpolation: 1.0,
+            graphics: None
         };
 
-        let mut rng = rand::thread_rng();
-        let range = Range::new(-1000.0, 1000.0);
-        
-        system.particle_pos.reserve(particle_count);
-        system.particle_vel.reserve(particle_count);
+        system.add_particles(particle_count);
+
+        system
+    }
 
-        for _ in 0..particle_count {
+    //Scatters count new particles in the initial volume, at rest.
+    fn add_particles(&mut self, count: usize) {
+        let initial = self.initial;
+
+        self.particle_pos.reserve(count);
+        self.particle_vel.reserve(count);
+
+        for _ in 0..count {
             let particle = Vec4 {
-                x : range.ind_sample(&mut rng),
-                y : range.ind_sample(&mut rng),
-                z : range.ind_sample(&mut rng),
+                x : sample_range(&mut self.rng, initial.min[0], initial.max[0]),
+                y : sample_range(&mut self.rng, initial.min[1], initial.max[1]),
+                z : sample_range(&mut self.rng, initial.min[2], initial.max[2]),
                 w : 0.0
             };
 
-            system.particle_pos.push(particle);
-            system.particle_vel.push(Vec4{x: 0.0, y: 0.0, z: 0.0, w:0.0});
+            self.particle_pos.push(particle);
+            self.particle_vel.push(Vec4{x: 0.0, y: 0.0, z: 0.0, w:0.0});
         }
+    }
 
-        system
+    pub fn particle_count(&self) -> usize {
+        self.particle_pos.len()
     }
 
-    pub fn init_graphics_resources(&mut self, work_groups: [u32; 3]) {     
-        self.compute_shader_work_groups = work_groups;
-        
-        self.draw_vao.bind();
-        let count = self.particle_pos.len();
-        let size = count * std::mem::size_of::<f32>();
-        self.possition_vbo.set_buffer_data_from_raw_ptr(self.particle_pos.as_ptr() as *const _, size as isize);
-        self.possition_vbo.describe_data(0, 4, 4*std::mem::size_of::<f32>(), 0);
-        self.draw_vao.unbind();
+    //Grows or shrinks the system. The first min(old, new) particles keep their current state
+    //(on the GPU too), added ones are scattered in the scene's initial volume.
+    pub fn set_particle_count(&mut self, count: usize) -> Result<(), GraphicsError> {
+        if let Some(ref gfx) = self.graphics {
+            gfx.work_groups(count)?;
+        }
+
+        let kept = self.particle_pos.len().min(count);
+        self.particle_pos.truncate(kept);
+        self.particle_vel.truncate(kept);
+        self.add_particles(count - kept);
+
+        if let Some(ref mut gfx) = self.graphics {
+            gfx.upload_particles(kept, &self.particle_pos, &self.particle_vel);
+        }
 
+        Ok(())
+    }
 
-        let quad_vertices: [f32; 24] = [ // vertex attributes for a quad that fills the entire screen in Normalized Device Coordinates.
-            // positions   // texCoords
-            -1.0,  1.0,  0.0, 1.0,
-            -1.0, -1.0,  0.0, 0.0,
-             1.0, -1.0,  1.0, 0.0,
+    pub fn emitter(&self) -> &Emitter {
+        &self.emitter
+    }
 
-            -1.0,  1.0,  0.0, 1.0,
-             1.0, -1.0,  1.0, 0.0,
-             1.0,  1.0,  1.0, 1.0
-        ];
-        self.screen_vao.bind();
-        self.fullscreen_quad_vbo.set_buffer_data(&quad_vertices);
-        self.fullscreen_quad_vbo.describe_data(0, 2, 4 * std::mem::size_of::<f32>(), 0);
-        self.fullscreen_quad_vbo.describe_data(1, 2, 4 * std::mem::size_of::<f32>(), 2 * std::mem::size_of::<f32>());
-        self.screen_vao.unbind();
+    //A mesh emitter has to be loaded already, see Emitter::load_mesh.
+    pub fn set_emitter(&mut self, emitter: Emitter) {
+        self.emitter = emitter;
+        self.spawn_carry = 0.0;
+        if let Some(ref mut gfx) = self.graphics {
+            gfx.upload_emitter_mesh(&self.emitter);
+        }
+    }
 
-        self.velocity_vbo.set_buffer_data_from_raw_ptr(self.particle_vel.as_ptr() as *const _, size as isize);
+    //Brings count dead particles back at position on the next update, with velocities picked
+    //from velocity (radial ones point away from position). Bursts come before the emitter, but
+    //only particles that are dead during that step can join one; the rest of the burst is dropped.
+    pub fn emit_burst(&mut self, position: [f32; 3], count: u32, velocity: VelocityMode) -> Result<(), String> {
+        velocity.validate()?;
+        self.bursts.push(Burst { position, count, velocity });
 
-        self.load_shaders();
+        Ok(())
     }
 
-    pub fn load_shaders(&mut self) {
-        println!("Loading shaders!");
+    //Adds a sphere collider, particles hit it from the next update on. A sphere with a non
+    //positive radius never collides.
+    pub fn add_sphere(&mut self, center: [f32; 3], radius: f32) -> SphereId {
+        self.collider_data.add_sphere(center, radius, None)
+    }
 
-        let input = [ShaderInputData::new(ShaderType::Vertex, "shaders/vertex_shader.v.glsl"),
-            ShaderInputData::new(ShaderType::Fragment, "shaders/pixel_shader.p.glsl"),
-            ShaderInputData::new(ShaderType::Geometry, "shaders/geometry_shader.g.glsl")];
-        self.draw_shader_program = shader::create_shader_from(&input);
+    //None brings back the classic response: reflect, then slow down every fast component of
+    //the velocity. Returns false when there is no sphere with this id (anymore).
+    pub fn set_sphere_material(&mut self, id: SphereId, material: Option<Material>) -> Result<bool, String> {
+        if let Some(ref material) = material {
+            material.validate()?;
+        }
 
-        let input = [ShaderInputData::new(ShaderType::Compute, "shaders/compute_shader.c.glsl")];
-        self.compute_shader_program = shader::create_shader_from(&input);
+        match self.collider_data.index_of(id) {
+            Some(index) => {
+                self.collider_data.sphere_materials[index] = material;
+                self.collider_data.dirty = true;
+                Ok(true)
+            }
+            None => Ok(false)
+        }
+    }
 
-        let input = [ShaderInputData::new(ShaderType::Vertex, "shaders/fullscreen_quad.v.glsl"),
-            ShaderInputData::new(ShaderType::Fragment, "shaders/copy_texture_to_quad.p.glsl")];
-        self.screen_program = shader::create_shader_from(&input);
+    //Returns false when there is no sphere with this id (anymore).
+    pub fn remove_sphere(&mut self, id: SphereId) -> bool {
+        match self.collider_data.index_of(id) {
+            Some(index) => {
+                self.collider_data.spheres.remove(index);
+                self.collider_data.sphere_velocities.remove(index);
+                self.collider_data.previous_centers.remove(index);
+                self.collider_data.sphere_materials.remove(index);
+                self.collider_data.sphere_ids.remove(index);
+                self.collider_data.dirty = true;
+                true
+            }
+            None => false
+        }
+    }
 
-        let input = [ShaderInputData::new(ShaderType::Vertex, "shaders/fullscreen_quad.v.glsl"),
-            ShaderInputData::new(ShaderType::Fragment, "shaders/blur_shader.p.glsl")];
+    //Returns false when there is no sphere with this id (anymore). Particles the sphere runs
+    //into during the next update are pushed along with the speed it moved at.
+    pub fn move_sphere(&mut self, id: SphereId, center: [f32; 3]) -> bool {
+        match self.collider_data.index_of(id) {
+            Some(index) => {
+                let sphere = &mut self.collider_data.spheres[index];
+                sphere.x = center[0];
+                sphere.y = center[1];
+                sphere.z = center[2];
+                self.collider_data.dirty = true;
+                true
+            }
+            None => false
+        }
+    }
 
-        self.blur_shader = shader::create_shader_from(&input);
+    pub fn spheres(&self) -> Vec<(SphereId, scene::Sphere)> {
+        self.collider_data.sphere_ids.iter()
+            .zip(self.collider_data.spheres.iter())
+            .zip(self.collider_data.sphere_materials.iter())
+            .map(|((&id, sphere), &material)| (id, scene::Sphere { center: [sphere.x, sphere.y, sphere.z], radius: sphere.w, material }))
+            .collect()
     }
-  
-    pub fn update(&mut self, dt: f64) {
 
-        self.compute_shader_program.bind();
-        {
-            self.compute_shader_program.set_uniform_1f("dt", dt as f32);
-            
-            let count = self.particle_pos.len();
-            self.compute_shader_program.set_uniform_1i("g_NumParticles", count as i32);
+    //Adds a collider, particles hit it from the next update on. Mesh colliders have to be
+    //loaded first, see Collider::load_mesh.
+    pub fn add_collider(&mut self, collider: Collider) -> Result<ColliderId, String> {
+        collider.validate()?;
+        Ok(self.collider_data.add_collider(collider))
+    }
+
+    //Returns false when there is no collider with this id (anymore).
+    pub fn remove_collider(&mut self, id: ColliderId) -> bool {
+        match self.collider_data.collider_index_of(id) {
+            Some(index) => {
+                self.collider_data.remove_collider(index);
+                true
+            }
+            None => false
+        }
+    }
 
-            self.compute_shader_program.set_uniform_1fv("sphereRadius", 20, &self.collider_data.sphere_radius);
+    //Places the collider for the next update, the particles it runs into are pushed along with
+    //the speed it moved at. Call it every frame to script the motion. Returns false when there
+    //is no collider with this id (anymore). Colliders with an animation ignore it.
+    pub fn set_collider_transform(&mut self, id: ColliderId, transform: ColliderTransform) -> bool {
+        match self.collider_data.collider_index_of(id) {
+            Some(index) => {
+                self.collider_data.transforms[index] = transform;
+                true
+            }
+            None => false
+        }
+    }
+
+    //Returns false when there is no collider with this id (anymore).
+    pub fn set_collider_material(&mut self, id: ColliderId, material: Material) -> Result<bool, String> {
+        material.validate()?;
+
+        match self.collider_data.collider_index_of(id) {
+            Some(index) => {
+                self.collider_data.shapes[index].set_material(material);
+                self.collider_data.resting[index].set_material(&material);
+                self.collider_data.moved[index].set_material(&material);
+                Ok(true)
+            }
+            None => Ok(false)
+        }
+    }
+
+    pub fn colliders(&self) -> Vec<(ColliderId, Collider)> {
+        self.collider_data.shape_ids.iter()
+            .cloned()
+            .zip(self.collider_data.shapes.iter().cloned())
+            .collect()
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn particle_positions(&self) -> &[Vec4] {
+        &self.particle_pos
+    }
+
+    pub fn particle_velocities(&self) -> &[Vec4] {
+        &self.particle_vel
+    }
+
+    pub fn init_graphics_resources(&mut self) -> Result<(), GraphicsError> {
+        let mut gfx = GraphicsResources {
+            programs: ProgramSet::new(&[Program::Draw, Program::Compute, Program::SpawnRank]),
+            possition_vbos: [VertexBufferObj::new(), VertexBufferObj::new()],
+            velocity_vbos: [VertexBufferObj::new(), VertexBufferObj::new()],
+            current: 0,
+            draw_vaos: [VertexArrayObj::new(), VertexArrayObj::new()],
+            max_work_groups: [0; 3],
+            spawn_ranks: VertexBufferObj::new(),
+            spawn_group_offsets: VertexBufferObj::new(),
+            bursts: VertexBufferObj::new(),
+            spheres: VertexBufferObj::new(),
+            sphere_velocities: VertexBufferObj::new(),
+            sphere_materials: VertexBufferObj::new(),
+            colliders: VertexBufferObj::new(),
+            collider_grids: VertexBufferObj::new(),
+            terrain_heights: VertexBufferObj::new(),
+            emitter_mesh: VertexBufferObj::new()
+        };
+
+        for (i, max) in gfx.max_work_groups.iter_mut().enumerate() {
+            let mut value = 0i32;
             unsafe {
-                let sphere_positions_buffer = std::slice::from_raw_parts(self.collider_data.sphere_positions.as_ptr() as *const f32, 60);
-                self.compute_shader_program.set_uniform_3fv("sphereOffsets", 20, sphere_positions_buffer);
+                gl::GetIntegeri_v(gl::MAX_COMPUTE_WORK_GROUP_COUNT, i as u32, &mut value);
             }
-            
-            let size_in_bytes = count * std::mem::size_of::<Vec4>();
+            *max = value as u32;
+        }
+        
+        let count = self.particle_pos.len();
+        gfx.upload_particles(0, &self.particle_pos, &self.particle_vel);
+        gfx.upload_emitter_mesh(&self.emitter);
+        gfx.upload_bursts(&[]);
+        gfx.upload_spheres(&self.collider_data);
+        self.collider_data.dirty = false;
+        gfx.upload_colliders(&self.collider_data.moved);
+        gfx.upload_collider_grids(&self.collider_data.grids);
+        self.collider_data.grids_dirty = false;
+        gfx.upload_terrain(self.terrain.as_ref());
+
+        self.graphics = Some(gfx);
+        self.load_shaders().map_err(GraphicsError::Shaders)?;
+        self.gfx_mut().work_groups(count)?;
+
+        Ok(())
+    }
+
+    //Rebuilds the draw and compute programs, see ProgramSet::load_all.
+    pub fn load_shaders(&mut self) -> Result<(), Vec<ShaderError>> {
+        self.gfx_mut().programs.load_all()
+    }
+
+    //Rebuilds the programs whose source files changed, None when nothing changed.
+    pub fn reload_changed_shaders(&mut self) -> Option<Result<(), Vec<ShaderError>>> {
+        self.gfx_mut().programs.reload_changed()
+    }
+
+    //Runs one simulation step on the GPU. Fails without touching the particles or the
+    //pending bursts when the compute programs can't cover the particle count, which after
+    //init_graphics_resources only happens if a reloaded compute shader changed its local_size.
+    pub fn update(&mut self, dt: f64) -> Result<(), GraphicsError> {
+        let count = self.particle_pos.len();
+        let (work_groups, rank_work_groups) = {
+            let gfx = self.graphics.as_ref().expect("init_graphics_resources was not called");
+            (gfx.work_groups(count)?, gfx.work_groups_for(Program::SpawnRank, count)?)
+        };
+        let lifetime_range = self.lifetime_range();
+        let lifetime_seed = self.lifetime_seed();
+        let spawn_budget = self.spawn_budget(dt as f32);
+        let bursts = burst_data(&std::mem::take(&mut self.bursts));
+        let burst_particles = bursts.last().map_or(0, |burst| burst.end);
+        self.collider_data.step(dt as f32);
+        let gfx = self.graphics.as_mut().expect("init_graphics_resources was not called");
+        //Bursts and the spawn budget go to the recycled particles with the lowest index,
+        //without either of them every recycled particle respawns and ranking is skipped.
+        let rank_spawns = spawn_budget.is_some() || burst_particles > 0;
+
+        if !bursts.is_empty() {
+            gfx.upload_bursts(&bursts);
+        }
+        if self.collider_data.dirty {
+            gfx.upload_spheres(&self.collider_data);
+            self.collider_data.dirty = false;
+        }
+        if !self.collider_data.moved.is_empty() {
+            gfx.upload_colliders(&self.collider_data.moved);
+        }
+        if self.collider_data.grids_dirty {
+            gfx.upload_collider_grids(&self.collider_data.grids);
+            self.collider_data.grids_dirty = false;
+        }
+
+        let size_in_bytes = count * std::mem::size_of::<Vec4>();
+        let (read, write) = (gfx.current, 1 - gfx.current);
+        unsafe {
+            gl::BindBufferRange(gl::SHADER_STORAGE_BUFFER, 0, 
+                gfx.possition_vbos[read].gl_handle(), 0, size_in_bytes as isize);
+            gl::BindBufferRange(gl::SHADER_STORAGE_BUFFER, 1, 
+                gfx.velocity_vbos[read].gl_handle(), 0, size_in_bytes as isize);
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 13, gfx.spawn_ranks.gl_handle());
+            gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 14, gfx.spawn_group_offsets.gl_handle());
+        }
+
+        if rank_spawns {
+            let rank_groups = count.div_ceil(RANK_GROUP_SIZE);
+            let program = gfx.programs.get(Program::SpawnRank);
+            program.bind();
+            program.set_uniform_1f("dt", dt as f32);
+            program.set_uniform_1i("g_NumParticles", count as i32);
+            program.set_uniform_1i("rankGroups", rank_groups as i32);
+            program.set_uniform_2f("lifetimeRange", &lifetime_range);
+            program.set_uniform_1ui("lifetimeSeed", lifetime_seed);
+            program.set_uniform_1f("emitterFloor", self.emitter.floor());
+            program.set_uniform_1i("rankPass", 0);
             unsafe {
-                gl::BindBufferRange(gl::SHADER_STORAGE_BUFFER, 0, 
-                    self.possition_vbo.gl_handle(), 0, size_in_bytes as isize);
-                gl::BindBufferRange(gl::SHADER_STORAGE_BUFFER, 1, 
-                    self.velocity_vbo.gl_handle(), 0, size_in_bytes as isize);
-
-                gl::DispatchCompute(self.compute_shader_work_groups[0], 
-                    self.compute_shader_work_groups[1], self.compute_shader_work_groups[2]);
-                gl::MemoryBarrier(gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT);
+                gl::DispatchCompute(rank_work_groups[0], rank_work_groups[1], rank_work_groups[2]);
+                gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT);
+            }
+            program.set_uniform_1i("rankPass", 1);
+            unsafe {
+                gl::DispatchCompute(1, 1, 1);
+                gl::MemoryBarrier(gl::SHADER_STORAGE_BARRIER_BIT);
+            }
+            program.unbind();
+        }
+
+        let program = gfx.programs.get(Program::Compute);
+        program.bind();
+        {
+            program.set_uniform_1f("dt", dt as f32);
+            program.set_uniform_1i("g_NumParticles", count as i32);
+
+            program.set_uniform_1f("gravity", self.gravity);
+            program.set_uniform_2f("g_Seed", &self.seed_offset);
+            program.set_uniform_2f("lifetimeRange", &lifetime_range);
+            program.set_uniform_1ui("lifetimeSeed", lifetime_seed);
+            self.bounds.set_uniforms(program);
+            program.set_uniform_1i("spawnBudget", spawn_budget.map_or(-1, |budget| budget as i32));
+            program.set_uniform_1i("burstCount", bursts.len() as i32);
+            program.set_uniform_1i("burstParticles", burst_particles as i32);
+            self.emitter.set_uniforms(program);
+
+            program.set_uniform_1i("sphereCount", self.collider_data.spheres.len() as i32);
+            program.set_uniform_1i("colliderCount", self.collider_data.moved.len() as i32);
+            match self.terrain {
+                Some(ref terrain) => terrain.set_uniforms(program),
+                None => program.set_uniform_2i("terrainSize", &[0, 0])
+            }
+
+            unsafe {
+                gl::BindBufferRange(gl::SHADER_STORAGE_BUFFER, 2, 
+                    gfx.possition_vbos[write].gl_handle(), 0, size_in_bytes as isize);
+                gl::BindBufferRange(gl::SHADER_STORAGE_BUFFER, 3, 
+                    gfx.velocity_vbos[write].gl_handle(), 0, size_in_bytes as isize);
+
+                gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 5, gfx.emitter_mesh.gl_handle());
+                gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 6, gfx.bursts.gl_handle());
+                gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 7, gfx.spheres.gl_handle());
+                gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 8, gfx.colliders.gl_handle());
+                gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 9, gfx.collider_grids.gl_handle());
+                gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 10, gfx.terrain_heights.gl_handle());
+                gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 11, gfx.sphere_velocities.gl_handle());
+                gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 12, gfx.sphere_materials.gl_handle());
+
+                gl::DispatchCompute(work_groups[0], work_groups[1], work_groups[2]);
+                gl::MemoryBarrier(gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT | gl::SHADER_STORAGE_BARRIER_BIT);
                 gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
             }
+            gfx.current = write;
 
         }
-        self.compute_shader_program.unbind();
+        program.unbind();
+
+        Ok(())
     }
 
-    pub fn render_particles(&mut self, cam: &Camera) {
+    //CPU port of shaders/compute_shader.c.glsl. It operates on particle_pos/particle_vel
+    //and does not touch the GPU buffers, so keep it in sync with the shader when changing either.
+    pub fn update_cpu(&mut self, dt: f64) {
+        let dt = dt as f32;
+        let spawn_budget = self.spawn_budget(dt);
+        let bursts = std::mem::take(&mut self.bursts);
+        let burst_particles: u32 = bursts.iter().map(|burst| burst.count).sum();
+        let mut burst_claimed = 0;
+        self.collider_data.step(dt);
+        let colliders = &self.collider_data;
+        let shapes = &colliders.moved;
+        let grids = &colliders.grids;
+        let terrain = &self.terrain;
+        let emitter = &self.emitter;
+        let emitter_floor = emitter.floor();
+        let bounds = &self.bounds;
+        let gravity = self.gravity;
+        let seed = self.seed_offset;
+        let lifetime_seed = self.lifetime_seed();
+        let lifetime_range = self.lifetime_range();
+        let mut spawned = 0;
+
+        for (index, (pos, vel)) in self.particle_pos.iter_mut().zip(self.particle_vel.iter_mut()).enumerate() {
+            let mut particle_pos = *pos;
+            let particle_velocity = *vel;
+            let mut stuck = is_stuck(&particle_pos);
 
-        self.draw_shader_program.bind();
+            //Update Velocity
+            let mut new_particle_velocity = particle_velocity;
+            let age = particle_velocity.w + dt;
+
+            //Recycle the particle if its life is over or our speed is too small, stuck particles
+            //are slow on purpose. A negative age marks a dead particle that is still waiting for
+            //its turn to respawn. Same test as needsRespawn in shaders/respawn.glsl.
+            let dead = particle_velocity.w < 0.0
+                || (lifetime_range[1] > 0.0 && age >= particle_lifetime(index, lifetime_range, lifetime_seed));
+            if dead || (!stuck && particle_pos.y < emitter_floor - 50.0 && new_particle_velocity.length() < 7.0) {
+                let rand1 = glsl_rand(particle_pos.x + seed[0], particle_pos.z + seed[1]);
+                let rand2 = glsl_rand(particle_pos.z + seed[0], particle_pos.x + seed[1]);
+                let u = emitter::random4(particle_pos.x + seed[0], particle_pos.z + seed[1]);
+                let v = emitter::random4(particle_pos.z + seed[0], particle_pos.y + seed[1]);
+
+                //Bursts go first, the emitter gets the particles that are left. Both are handed
+                //out in index order, like the ranks of shaders/spawn_rank.c.glsl.
+                let (position, velocity) = if burst_claimed < burst_particles {
+                    let mut end = 0;
+                    let burst = bursts.iter().find(|burst| {
+                        end += burst.count;
+                        burst_claimed < end
+                    }).expect("burst_claimed is below the total");
+                    burst_claimed += 1;
+                    (burst.position, burst.velocity.sample(burst.position, burst.position, rand1, rand2, v))
+                } else if spawn_budget.is_none_or(|budget| spawned < budget) {
+                    spawned += 1;
+                    emitter.emit(rand1, rand2, u, v)
+                } else {
+                    *vel = Vec4 { x: 0.0, y: 0.0, z: 0.0, w: -1.0 };
+                    continue;
+                };
+                particle_pos.x = position[0];
+                particle_pos.y = position[1];
+                particle_pos.z = position[2];
+                new_particle_velocity.x = velocity[0];
+                new_particle_velocity.y = velocity[1];
+                new_particle_velocity.z = velocity[2];
+                new_particle_velocity.w = 0.0;
+                stuck = false;
+            } else {
+                //Just update the particle
+                particle_pos.x += particle_velocity.x * dt;
+                particle_pos.y += particle_velocity.y * dt;
+                particle_pos.z += particle_velocity.z * dt;
+                new_particle_velocity.y -= gravity * dt;
+                new_particle_velocity.w = age;
+            }
+
+            //Collisions, a particle that touches a killing material or face dies.
+            //Ground Bounce on the terrain.
+            if let Some(ref terrain) = *terrain {
+                let mut position = [particle_pos.x, particle_pos.y, particle_pos.z];
+                let mut velocity = [new_particle_velocity.x, new_particle_velocity.y, new_particle_velocity.z];
+                terrain.collide(&mut position, &mut velocity);
+                particle_pos.x = position[0];
+                particle_pos.y = position[1];
+                particle_pos.z = position[2];
+                new_particle_velocity.x = velocity[0];
+                new_particle_velocity.y = velocity[1];
+                new_particle_velocity.z = velocity[2];
+            }
+
+            //Walls, floor and ceiling
+            let mut position = [particle_pos.x, particle_pos.y, particle_pos.z];
+            let mut velocity = [new_particle_velocity.x, new_particle_velocity.y, new_particle_velocity.z];
+            let mut alive = bounds.apply(&mut position, &mut velocity);
+            particle_pos.x = position[0];
+            particle_pos.y = position[1];
+            particle_pos.z = position[2];
+            new_particle_velocity.x = velocity[0];
+            new_particle_velocity.y = velocity[1];
+            new_particle_velocity.z = velocity[2];
+
+            //Try to find the closest sphere to our particle.
+            let mut min_dist = 10000.0;
+            let mut closest_sphere_idx = None;
+            for (i, sphere) in colliders.spheres.iter().enumerate() {
+                let dist = distance_field_circle(&particle_pos, &Vec3 { x: sphere.x, y: sphere.y, z: sphere.z }, sphere.w);
+                if dist < min_dist && dist < collider::contact_distance(stuck) {
+                    min_dist = dist;
+                    closest_sphere_idx = Some(i);
+                }
+            }
+
+            if let Some(i) = closest_sphere_idx {
+                let sphere = colliders.spheres[i];
+                let center = Vec3 { x: sphere.x, y: sphere.y, z: sphere.z };
+                let sphere_velocity = colliders.sphere_velocities[i];
+                let normal = Vec3 {
+                    x: particle_pos.x - center.x,
+                    y: particle_pos.y - center.y,
+                    z: particle_pos.z - center.z
+                }.normalize();
+
+                //Move the particle away from the collision just a bit.
+                let offset = sphere.w + 0.1;
+                particle_pos.x = center.x + normal.x * offset;
+                particle_pos.y = center.y + normal.y * offset;
+                particle_pos.z = center.z + normal.z * offset;
+
+                match colliders.sphere_materials[i] {
+                    None => {
+                        //Reflect our speed, relative to the moving sphere
+                        new_particle_velocity.x -= sphere_velocity.x;
+                        new_particle_velocity.y -= sphere_velocity.y;
+                        new_particle_velocity.z -= sphere_velocity.z;
+                        let d = 2.0 * (new_particle_velocity.x * normal.x
+                            + new_particle_velocity.y * normal.y
+                            + new_particle_velocity.z * normal.z);
+                        new_particle_velocity.x -= d * normal.x;
+                        new_particle_velocity.y -= d * normal.y;
+                        new_particle_velocity.z -= d * normal.z;
+                        if new_particle_velocity.x.abs() > 2.0 {
+                            new_particle_velocity.x *= 0.2;
+                        }
+                        if new_particle_velocity.y.abs() > 2.0 {
+                            new_particle_velocity.y *= 0.2;
+                        }
+                        if new_particle_velocity.z.abs() > 2.0 {
+                            new_particle_velocity.z *= 0.2;
+                        }
+                        new_particle_velocity.x += sphere_velocity.x;
+                        new_particle_velocity.y += sphere_velocity.y;
+                        new_particle_velocity.z += sphere_velocity.z;
+                    }
+                    Some(material) => {
+                        let mut velocity = [new_particle_velocity.x, new_particle_velocity.y, new_particle_velocity.z];
+                        alive = alive && material.respond([particle_pos.x, particle_pos.y, particle_pos.z], [normal.x, normal.y, normal.z],
+                            [sphere_velocity.x, sphere_velocity.y, sphere_velocity.z], &mut velocity, &mut stuck, seed);
+                        new_particle_velocity.x = velocity[0];
+                        new_particle_velocity.y = velocity[1];
+                        new_particle_velocity.z = velocity[2];
+                    }
+                }
+            }
+
+            for collider in shapes {
+                if !alive {
+                    break;
+                }
+                let mut position = [particle_pos.x, particle_pos.y, particle_pos.z];
+                let mut velocity = [new_particle_velocity.x, new_particle_velocity.y, new_particle_velocity.z];
+                alive = collider.collide(&mut position, &mut velocity, &mut stuck, grids, seed);
+                particle_pos.x = position[0];
+                particle_pos.y = position[1];
+                particle_pos.z = position[2];
+                new_particle_velocity.x = velocity[0];
+                new_particle_velocity.y = velocity[1];
+                new_particle_velocity.z = velocity[2];
+            }
+
+            //Dead particles wait for their turn to respawn, like the ones the emitter has no room for.
+            if !alive {
+                new_particle_velocity = Vec4 { x: 0.0, y: 0.0, z: 0.0, w: -1.0 };
+                stuck = false;
+            }
+
+            //SetColor based on the velocity, stuck particles store -1 - speed.
+            let speed = new_particle_velocity.length();
+            particle_pos.w = if stuck { -1.0 - speed } else { speed };
+
+            *pos = particle_pos;
+            *vel = new_particle_velocity;
+        }
+    }
+
+    //0.0 draws the particles where they were before the last update, 1.0 (the default) where
+    //they are now. Lets a fixed timestep simulation render smoothly at any frame rate.
+    pub fn set_interpolation(&mut self, alpha: f32) {
+        self.interpolation = alpha.clamp(0.0, 1.0);
+    }
+
+    //[min, max] lifetime as the shaders expect it, [0, 0] when particles don't age out.
+    fn lifetime_range(&self) -> [f32; 2] {
+        self.emitter.lifetime.unwrap_or([0.0, 0.0])
+    }
+
+    //Seed of the lifetime hash, a different one for every system of the scene.
+    fn lifetime_seed(&self) -> u32 {
+        self.seed_offset[0].to_bits()
+    }
+
+    //How many particles may respawn during a step of dt seconds, None when the emitter has no rate.
+    fn spawn_budget(&mut self, dt: f32) -> Option<u32> {
+        let rate = self.emitter.rate?;
+        self.spawn_carry += rate * dt;
+        let budget = self.spawn_carry.floor();
+        self.spawn_carry -= budget;
+
+        Some(budget as u32)
+    }
+
+    //Draws the particles into the currently bound framebuffer, see Renderer::render.
+    pub fn render_particles(&self, cam: &Camera) {
+        let lifetime_range = self.lifetime_range();
+        let gfx = self.graphics.as_ref().expect("init_graphics_resources was not called");
+
+        let program = gfx.programs.get(Program::Draw);
+        program.bind();
 
         let elapsed = self.start.elapsed().as_milis();
         let colorg = (elapsed % 1000) as f32 / 1000.0f32;
-        self.draw_shader_program.set_uniform4f("vtx_color", &[0.3, colorg, 0.3, 1.0]);
+        program.set_uniform4f("vtx_color", &[0.3, colorg, 0.3, 1.0]);
         
-        self.draw_shader_program.set_uniform_matrix4("view_from_world", cam.view_from_world.as_ref());
-        self.draw_shader_program.set_uniform_matrix4("proj_from_view", cam.proj_from_view.as_ref());
+        program.set_uniform_matrix4("view_from_world", cam.view_from_world.as_ref());
+        program.set_uniform_matrix4("proj_from_view", cam.proj_from_view.as_ref());
+        program.set_uniform_1f("interpolation", self.interpolation);
+        program.set_uniform_2f("lifetimeRange", &lifetime_range);
+        program.set_uniform_1ui("lifetimeSeed", self.lifetime_seed());
+
+        let colors = &self.appearance.color_over_life;
+        let color_values: Vec<f32> = colors.iter().flat_map(|color| color.iter().cloned()).collect();
+        program.set_uniform_4fv("colorOverLife", colors.len() as i32, &color_values);
+        program.set_uniform_1i("colorPoints", colors.len() as i32);
+        let sizes = &self.appearance.size_over_life;
+        program.set_uniform_1fv("sizeOverLife", sizes.len() as i32, sizes);
+        program.set_uniform_1i("sizePoints", sizes.len() as i32);
 
         unsafe {
-           self.draw_vao.bind();
+            gfx.draw_vaos[gfx.current].bind();
             gl::DrawArrays(gl::POINTS, 0, self.particle_pos.len() as i32);
-            self.draw_vao.unbind();
+            gfx.draw_vaos[gfx.current].unbind();
         }    
-        self.draw_shader_program.unbind();
+        program.unbind();
     }
 
-    pub fn render(&mut self, cam: &Camera) {
-        
-        //First pass
-        self.frame_buffer.bind();
-        unsafe {
-            gl::Viewport(0, 0, 1600, 900);
-            gl::Enable(gl::DEPTH_TEST);
-            
-            let attachments: [u32; 2] = [gl::COLOR_ATTACHMENT0, gl::COLOR_ATTACHMENT1];
-            gl::DrawBuffers(2, attachments.as_ptr() as * const _);
+    fn gfx_mut(&mut self) -> &mut GraphicsResources {
+        self.graphics.as_mut().expect("init_graphics_resources was not called")
+    }
+}
 
-            let color: [f32; 4] = [0.003, 0.003, 0.003, 1.0];
-            let black: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
-            gl::ClearBufferfv(gl::COLOR, 0, color.as_ptr() as *const _);
-            gl::ClearBufferfv(gl::COLOR, 1, black.as_ptr() as *const _);
-            //gl::ClearColor(0.003, 0.003, 0.003, 1.0); 
-            gl::Clear(gl::DEPTH_BUFFER_BIT); 
+
+impl ProgramSource for Program {
+    fn inputs(&self) -> Vec<ShaderInputData> {
+        match *self {
+            Program::Draw => vec![ShaderInputData::new(ShaderType::Vertex, "shaders/vertex_shader.v.glsl")
+                    .define("MAX_CURVE_POINTS", scene::MAX_CURVE_POINTS),
+                ShaderInputData::new(ShaderType::Fragment, "shaders/pixel_shader.p.glsl"),
+                ShaderInputData::new(ShaderType::Geometry, "shaders/geometry_shader.g.glsl")],
+            Program::Compute => vec![ShaderInputData::new(ShaderType::Compute, "shaders/compute_shader.c.glsl")
+                .define("LOCAL_SIZE_X", LOCAL_SIZE[0])
+                .define("LOCAL_SIZE_Y", LOCAL_SIZE[1])
+                .define("RANK_GROUP_SIZE", RANK_GROUP_SIZE)],
+            Program::SpawnRank => vec![ShaderInputData::new(ShaderType::Compute, "shaders/spawn_rank.c.glsl")
+                .define("RANK_GROUP_SIZE", RANK_GROUP_SIZE)]
         }
+    }
+}
 
-        self.render_particles(cam);    
-        self.frame_buffer.unbind();
 
-        {
-            self.blur_shader.bind();
-            self.screen_vao.bind();
-            unsafe { 
-                gl::Disable(gl::DEPTH_TEST);
-            }
+impl GraphicsResources {
+    //Replaces the particle buffers with ones sized for positions.len() particles. The first
+    //kept particles are copied from the current GPU state, the rest come from positions/velocities.
+    //Both halves start with the same state, so the next frame has nothing to interpolate.
+    fn upload_particles(&mut self, kept: usize, positions: &[Vec4], velocities: &[Vec4]) {
+        let mut possition_vbos = [VertexBufferObj::new(), VertexBufferObj::new()];
+        let mut velocity_vbos = [VertexBufferObj::new(), VertexBufferObj::new()];
+        for i in 0..2 {
+            fill_particle_buffer(&mut possition_vbos[i], &self.possition_vbos[self.current], kept, positions);
+            fill_particle_buffer(&mut velocity_vbos[i], &self.velocity_vbos[self.current], kept, velocities);
+        }
 
-            //Do the blur
-            let blur_passes = 5;
-            let mut vertical = false;
-            let mut first_pass = true;
-            for _ in 0..blur_passes {
-                let (source, dest) = match vertical {
-                    false => (0, 1),
-                    true => (1, 0)
-                };
-                
-                self.blur_frame_buffers[dest].bind();
-                let mut color_buffer = self.blur_frame_buffers[source].get_color_texture();
-                if first_pass {
-                    color_buffer = self.frame_buffer.get_highlights_texture();
-                    first_pass = false;
-                }
-                color_buffer.bind();
-                self.blur_shader.set_uniform_1i("vertical", vertical as i32);
-                unsafe {
-                    gl::DrawArrays(gl::TRIANGLES, 0, 6);
-                }
+        self.possition_vbos = possition_vbos;
+        self.velocity_vbos = velocity_vbos;
+        self.current = 0;
 
-                color_buffer.unbind();
-                
-                vertical = !vertical;
-            }
+        //Like the emitter mesh, the rank buffers are never empty.
+        let ranks = vec![0u32; positions.len().max(1)];
+        let group_offsets = vec![0u32; positions.len().div_ceil(RANK_GROUP_SIZE).max(1)];
+        unsafe {
+            self.spawn_ranks.set_buffer_data_from_raw_ptr(ranks.as_ptr() as *const _, std::mem::size_of_val(&ranks[..]) as isize);
+            self.spawn_group_offsets.set_buffer_data_from_raw_ptr(group_offsets.as_ptr() as *const _, std::mem::size_of_val(&group_offsets[..]) as isize);
+        }
 
-            self.screen_vao.unbind();
-            self.blur_shader.unbind();
+        for i in 0..2 {
+            self.draw_vaos[i].bind();
+            self.possition_vbos[i].bind();
+            self.possition_vbos[i].describe_data(0, 4, std::mem::size_of::<Vec4>(), 0);
+            self.possition_vbos[1 - i].bind();
+            self.possition_vbos[1 - i].describe_data(1, 4, std::mem::size_of::<Vec4>(), 0);
+            self.velocity_vbos[i].bind();
+            self.velocity_vbos[i].describe_data(2, 4, std::mem::size_of::<Vec4>(), 0);
+            self.draw_vaos[i].unbind();
         }
+    }
 
-        //Final pass
-        unsafe{ 
-            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
-            gl::ClearColor(1.0, 1.0, 1.0, 1.0);
-            gl::Clear(gl::COLOR_BUFFER_BIT);
+    //The buffer always holds at least one triangle, GL doesn't allow binding an empty one.
+    fn upload_emitter_mesh(&mut self, emitter: &Emitter) {
+        let mut data = emitter.mesh_buffer_data();
+        if data.is_empty() {
+            data.resize(12, 0.0);
         }
+        self.emitter_mesh.set_buffer_data(&data);
+    }
 
-        self.screen_program.bind();
+    //Like the emitter mesh, the buffer is never empty.
+    fn upload_spheres(&mut self, collider_data: &ColliderData) {
+        let empty = [Vec4 { x: 0.0, y: 0.0, z: 0.0, w: 0.0 }];
+        let spheres = if collider_data.spheres.is_empty() { &empty[..] } else { &collider_data.spheres[..] };
+        let velocities = if collider_data.spheres.is_empty() { &empty[..] } else { &collider_data.sphere_velocities[..] };
+        let mut materials: Vec<MaterialData> = collider_data.sphere_materials.iter()
+            .map(|material| material.map_or_else(MaterialData::splash, |material| material.data()))
+            .collect();
+        if materials.is_empty() {
+            materials.push(MaterialData::splash());
+        }
         unsafe {
-            self.screen_vao.bind();
-            gl::Disable(gl::DEPTH_TEST);
-            self.screen_program.set_uniform_1i("screenTexture", 0);
-            self.screen_program.set_uniform_1i("bloom", 1);
+            self.spheres.set_buffer_data_from_raw_ptr(spheres.as_ptr() as *const _, std::mem::size_of_val(spheres) as isize);
+            self.sphere_velocities.set_buffer_data_from_raw_ptr(velocities.as_ptr() as *const _, std::mem::size_of_val(velocities) as isize);
+            self.sphere_materials.set_buffer_data_from_raw_ptr(materials.as_ptr() as *const _, (materials.len() * std::mem::size_of::<MaterialData>()) as isize);
+        }
+    }
+
+    //Like the emitter mesh, the buffer is never empty.
+    fn upload_colliders(&mut self, colliders: &[ColliderSdf]) {
+        let empty = [ColliderSdf::default()];
+        let colliders = if colliders.is_empty() { &empty[..] } else { colliders };
+        unsafe {
+            self.colliders.set_buffer_data_from_raw_ptr(colliders.as_ptr() as *const _, std::mem::size_of_val(colliders) as isize);
+        }
+    }
 
-            let color_buffer = self.frame_buffer.get_color_texture();
-            gl::ActiveTexture(gl::TEXTURE0);
-            color_buffer.bind();
+    //Like the emitter mesh, the buffer is never empty.
+    fn upload_collider_grids(&mut self, grids: &[f32]) {
+        let grids = if grids.is_empty() { &[0.0][..] } else { grids };
+        self.collider_grids.set_buffer_data(grids);
+    }
 
-            let blured_texture = self.blur_frame_buffers[1].get_color_texture();
-            gl::ActiveTexture(gl::TEXTURE1);
-            blured_texture.bind();
+    //Like the emitter mesh, the buffer is never empty.
+    fn upload_terrain(&mut self, terrain: Option<&Terrain>) {
+        let heights = terrain.map_or(&[][..], |terrain| terrain.heights());
+        let heights = if heights.is_empty() { &[0.0][..] } else { heights };
+        self.terrain_heights.set_buffer_data(heights);
+    }
 
-            gl::DrawArrays(gl::TRIANGLES, 0, 6);
-            
-            color_buffer.unbind();
-            self.screen_vao.unbind();
+    //Like the emitter mesh, the buffer is never empty.
+    fn upload_bursts(&mut self, bursts: &[BurstData]) {
+        let empty = [BurstData::default()];
+        let bursts = if bursts.is_empty() { &empty[..] } else { bursts };
+        unsafe {
+            self.bursts.set_buffer_data_from_raw_ptr(bursts.as_ptr() as *const _, std::mem::size_of_val(bursts) as isize);
         }
-        self.screen_program.unbind();
+    }
+
+    //Dispatch size that covers count particles with the compute shader's local_size. The shader
+    //flattens the invocation id as x + y * width, so groups fill x first and spill over into y.
+    fn work_groups(&self, count: usize) -> Result<[u32; 3], GraphicsError> {
+        self.work_groups_for(Program::Compute, count)
+    }
+
+    //Like work_groups, for any of the compute programs.
+    fn work_groups_for(&self, program: Program, count: usize) -> Result<[u32; 3], GraphicsError> {
+        let local_size = self.programs.get(program).compute_work_group_size();
+        let per_group = local_size.iter().map(|&size| size as u64).product::<u64>().max(1);
+        let needed = (count as u64).div_ceil(per_group);
+
+        let x = needed.min(self.max_work_groups[0] as u64);
+        let y = if x == 0 { 0 } else { needed.div_ceil(x) };
+        let work_groups = [x, y, 1];
+
+        if y > self.max_work_groups[1] as u64 {
+            return Err(GraphicsError::TooManyParticles {
+                count,
+                work_groups,
+                max_work_groups: self.max_work_groups
+            });
+        }
+
+        Ok([x as u32, y as u32, 1])
     }
 }
 
 
 impl ColliderData {
-    fn new() -> ColliderData {
-        let mut colider_data = ColliderData {
-            sphere_positions: [Vec3{x: 0.0, y: 0.0, z: 0.0}; 20],
-            sphere_radius: [0.0; 20]
-        };
-
-        let mut rng = rand::thread_rng();
+    fn new<R: Rng>(rng: &mut R) -> ColliderData {
         let position_range = Range::new(-1000.0, 1000.0);
         let radius_range = Range::new(100, 400);
 
-        for i in 0 .. 20 {
-            let pos = &mut colider_data.sphere_positions[i];
-            pos.x = position_range.ind_sample(&mut rng) as f32;
-            pos.y = 0.0;
-            pos.z = position_range.ind_sample(&mut rng) as f32;
-            colider_data.sphere_radius[i] = radius_range.ind_sample(&mut rng) as f32;
+        let mut colider_data = ColliderData::from_spheres(&[]);
+        for _ in 0..RANDOM_SPHERES {
+            let x = position_range.ind_sample(rng) as f32;
+            let z = position_range.ind_sample(rng) as f32;
+            let radius = radius_range.ind_sample(rng) as f32;
+            colider_data.add_sphere([x, 0.0, z], radius, None);
+        }
+
+        colider_data
+    }
+
+    fn from_spheres(spheres: &[scene::Sphere]) -> ColliderData {
+        let mut colider_data = ColliderData {
+            spheres: Vec::with_capacity(spheres.len()),
+            sphere_velocities: Vec::with_capacity(spheres.len()),
+            previous_centers: Vec::with_capacity(spheres.len()),
+            sphere_materials: Vec::with_capacity(spheres.len()),
+            sphere_ids: Vec::with_capacity(spheres.len()),
+            next_sphere_id: 0,
+            dirty: true,
+            shapes: Vec::new(),
+            shape_ids: Vec::new(),
+            transforms: Vec::new(),
+            next_collider_id: 0,
+            resting: Vec::new(),
+            moved: Vec::new(),
+            grids: Vec::new(),
+            grids_dirty: true,
+            time: 0.0
+        };
+
+        for sphere in spheres {
+            colider_data.add_sphere(sphere.center, sphere.radius, sphere.material);
         }
 
         colider_data
     }
-}
\ No newline at end of file
+
+    fn add_sphere(&mut self, center: [f32; 3], radius: f32, material: Option<Material>) -> SphereId {
+        let id = SphereId(self.next_sphere_id);
+        self.next_sphere_id += 1;
+        self.spheres.push(Vec4 { x: center[0], y: center[1], z: center[2], w: radius });
+        self.sphere_velocities.push(Vec4 { x: 0.0, y: 0.0, z: 0.0, w: 0.0 });
+        self.previous_centers.push(center);
+        self.sphere_materials.push(material);
+        self.sphere_ids.push(id);
+        self.dirty = true;
+
+        id
+    }
+
+    fn index_of(&self, id: SphereId) -> Option<usize> {
+        self.sphere_ids.iter().position(|&sphere_id| sphere_id == id)
+    }
+
+    fn add_collider(&mut self, collider: Collider) -> ColliderId {
+        let id = ColliderId(self.next_collider_id);
+        self.next_collider_id += 1;
+        let resting = collider.sdf(&mut self.grids);
+        self.shapes.push(collider);
+        self.shape_ids.push(id);
+        self.transforms.push(ColliderTransform::default());
+        self.resting.push(resting);
+        let index = self.shapes.len() - 1;
+        self.moved.push(resting.transformed(self.shapes[index].pivot(), &self.transform(index)));
+        self.grids_dirty = true;
+
+        id
+    }
+
+    //The grids are packed again, so the other mesh colliders don't keep the samples alive.
+    fn remove_collider(&mut self, index: usize) {
+        self.shapes.remove(index);
+        self.shape_ids.remove(index);
+        self.transforms.remove(index);
+        self.moved.remove(index);
+
+        let mut grids = Vec::new();
+        self.resting = self.shapes.iter().map(|collider| collider.sdf(&mut grids)).collect();
+        self.grids = grids;
+        self.grids_dirty = true;
+    }
+
+    fn collider_index_of(&self, id: ColliderId) -> Option<usize> {
+        self.shape_ids.iter().position(|&collider_id| collider_id == id)
+    }
+
+    //Where shapes[index] is at the current time.
+    fn transform(&self, index: usize) -> ColliderTransform {
+        match self.shapes[index].animation {
+            Some(ref animation) => animation.transform_at(self.time),
+            None => self.transforms[index]
+        }
+    }
+
+    //Advances the animations by dt seconds and derives the velocities of everything that moved
+    //since the last step.
+    fn step(&mut self, dt: f32) {
+        self.time += dt;
+
+        for index in 0..self.spheres.len() {
+            let sphere = self.spheres[index];
+            let previous = self.previous_centers[index];
+            let velocity = if dt > 0.0 {
+                Vec4 { x: (sphere.x - previous[0]) / dt, y: (sphere.y - previous[1]) / dt, z: (sphere.z - previous[2]) / dt, w: 0.0 }
+            } else {
+                Vec4 { x: 0.0, y: 0.0, z: 0.0, w: 0.0 }
+            };
+
+            let old = self.sphere_velocities[index];
+            if (old.x, old.y, old.z) != (velocity.x, velocity.y, velocity.z) {
+                self.sphere_velocities[index] = velocity;
+                self.dirty = true;
+            }
+            self.previous_centers[index] = [sphere.x, sphere.y, sphere.z];
+        }
+
+        for index in 0..self.shapes.len() {
+            let mut moved = self.resting[index].transformed(self.shapes[index].pivot(), &self.transform(index));
+            moved.set_motion(&self.moved[index], dt);
+            self.moved[index] = moved;
+        }
+    }
+}
+
+
+impl Vec3 {
+    fn length(&self) -> f32 {
+        (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+
+    fn normalize(&self) -> Vec3 {
+        let len = self.length();
+        Vec3 { x: self.x / len, y: self.y / len, z: self.z / len }
+    }
+}
+
+
+impl From<[f32; 3]> for Vec3 {
+    fn from(v: [f32; 3]) -> Vec3 {
+        Vec3 { x: v[0], y: v[1], z: v[2] }
+    }
+}
+
+
+impl Vec4 {
+    //Length of the xyz part, w holds the speed for positions (see is_stuck) and the age for velocities.
+    fn length(&self) -> f32 {
+        (self.x * self.x + self.y * self.y + self.z * self.z).sqrt()
+    }
+}
+
+
+//Samples the distance field and returns the value for point p
+//For the moment just use a distance function.
+fn distance_field_circle(p: &Vec4, sphere_offset: &Vec3, sphere_radius: f32) -> f32 {
+    Vec3 { x: sphere_offset.x - p.x, y: sphere_offset.y - p.y, z: sphere_offset.z - p.z }.length() - sphere_radius
+}
+
+//Same as isStuck() in shaders/respawn.glsl: stuck particles (see Material::stick) store
+//-1 - speed in the w of their position instead of the speed.
+fn is_stuck(position: &Vec4) -> bool {
+    position.w < 0.0
+}
+
+//Allocates buffer for data.len() particles, copies the first kept ones from old and the rest from data.
+fn fill_particle_buffer(buffer: &mut VertexBufferObj, old: &VertexBufferObj, kept: usize, data: &[Vec4]) {
+    let size = std::mem::size_of_val(data) as isize;
+    let kept_size = std::mem::size_of_val(&data[..kept]) as isize;
+    unsafe {
+        buffer.set_buffer_data_from_raw_ptr(std::ptr::null(), size);
+        buffer.set_buffer_sub_data_from_raw_ptr(kept_size, data[kept..].as_ptr() as *const _, size - kept_size);
+    }
+    if kept > 0 {
+        buffer.copy_from(old, kept_size);
+    }
+}
+
+
+fn burst_data(bursts: &[Burst]) -> Vec<BurstData> {
+    let mut end = 0;
+    bursts.iter()
+        .map(|burst| {
+            end += burst.count;
+            let (velocity_mode, velocity_a, velocity_b, velocity_params) = burst.velocity.shader_values();
+            BurstData {
+                position: burst.position,
+                end,
+                velocity_a,
+                velocity_mode,
+                velocity_b,
+                velocity_params,
+                ..BurstData::default()
+            }
+        })
+        .collect()
+}
+
+
+//Same hash as rand() in the compute shader.
+#[allow(clippy::excessive_precision)]
+fn glsl_rand(x: f32, y: f32) -> f32 {
+    let v = (x * 12.9898 + y * 78.233).sin() * 43758.5453;
+    (v - v.floor()) * 1000.0
+}
+
+//Same as wangHash() in shaders/lifetime.glsl.
+fn wang_hash(mut x: u32) -> u32 {
+    x = (x ^ 61) ^ (x >> 16);
+    x = x.wrapping_mul(9);
+    x ^= x >> 4;
+    x = x.wrapping_mul(0x27d4_eb2d);
+    x ^= x >> 15;
+    x
+}
+
+//Same as particleLifetime() in shaders/lifetime.glsl.
+fn particle_lifetime(index: usize, range: [f32; 2], seed: u32) -> f32 {
+    let r = (wang_hash(index as u32 ^ seed) >> 8) as f32 / 16_777_216.0;
+    range[0] + (range[1] - range[0]) * r
+}
+
+fn sample_range<R: rand::Rng>(rng: &mut R, min: f32, max: f32) -> f32 {
+    if min < max { Range::new(min, max).ind_sample(rng) } else { min }
+}
+
+
+impl fmt::Display for GraphicsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GraphicsError::Shaders(ref errors) => {
+                let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+                write!(f, "{}", messages.join("\n"))
+            }
+            GraphicsError::TooManyParticles {
+                count,
+                ref work_groups,
+                ref max_work_groups
+            } => write!(f, "{} particles need {:?} work groups, the GPU supports at most {:?}",
+                count, work_groups, max_work_groups)
+        }
+    }
+}
+
+
+impl std::error::Error for GraphicsError {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use collider::ColliderShape;
+    use scene::{Bounds, BoundsFace, Sphere};
+
+    const DT: f64 = 0.5;
+
+    fn vec4(x: f32, y: f32, z: f32, w: f32) -> Vec4 {
+        Vec4 { x, y, z, w }
+    }
+
+    fn assert_close(actual: &Vec4, expected: [f32; 4]) {
+        let actual = [actual.x, actual.y, actual.z, actual.w];
+        for i in 0..4 {
+            assert!((actual[i] - expected[i]).abs() < 1e-3, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    fn assert_position(system: &ParticleSystem, index: usize, expected: [f32; 3]) {
+        let actual = system.particle_positions()[index];
+        assert_close(&vec4(actual.x, actual.y, actual.z, 0.0), [expected[0], expected[1], expected[2], 0.0]);
+    }
+
+    //System built from scene (no spheres unless it lists some) holding exactly the given
+    //position/velocity pairs.
+    fn system_with(scene: Scene, particles: &[(Vec4, Vec4)]) -> ParticleSystem {
+        let mut system = ParticleSystem::from_scene(&Scene {
+            particle_count: particles.len(),
+            seed: Some(7),
+            spheres: scene.spheres.clone().or_else(|| Some(Vec::new())),
+            ..scene
+        });
+        system.particle_pos = particles.iter().map(|&(position, _)| position).collect();
+        system.particle_vel = particles.iter().map(|&(_, velocity)| velocity).collect();
+
+        system
+    }
+
+    #[test]
+    fn integrates_gravity() {
+        let scene = Scene { gravity: 10.0, ..Scene::default() };
+        let mut system = system_with(scene, &[(vec4(0.0, 1000.0, 0.0, 0.0), vec4(10.0, 20.0, -5.0, 0.0))]);
+
+        system.update_cpu(DT);
+        //The position moves with the velocity from before the step.
+        assert_position(&system, 0, [5.0, 1010.0, -2.5]);
+        assert_close(&system.particle_velocities()[0], [10.0, 15.0, -5.0, 0.5]);
+
+        system.update_cpu(DT);
+        assert_position(&system, 0, [10.0, 1017.5, -5.0]);
+        assert_close(&system.particle_velocities()[0], [10.0, 10.0, -5.0, 1.0]);
+    }
+
+    #[test]
+    fn recycles_grounded_and_slow_particles() {
+        let mut system = system_with(Scene::default(), &[
+            (vec4(0.0, 0.0, 0.0, 0.0), vec4(0.0, 0.0, 0.0, 0.0)),
+            (vec4(0.0, 100.0, 0.0, 0.0), vec4(1.0, 0.0, 0.0, 0.0)),
+            (vec4(0.0, 1000.0, 0.0, 0.0), vec4(1.0, 0.0, 0.0, 0.0))
+        ]);
+
+        system.update_cpu(DT);
+        for index in 0..2 {
+            let (position, velocity) = (system.particle_positions()[index], system.particle_velocities()[index]);
+            assert!(position.x >= -700.0 && position.x <= 700.0, "{:?}", position);
+            assert!(position.y >= 500.0 && position.y <= 550.0, "{:?}", position);
+            assert!(position.z >= -700.0 && position.z <= 700.0, "{:?}", position);
+            assert!([velocity.x, velocity.y, velocity.z].iter().all(|v| v.abs() <= 5.0), "{:?}", velocity);
+        }
+        //Slow particles high up keep falling.
+        assert_position(&system, 2, [0.5, 1000.0, 0.0]);
+    }
+
+    #[test]
+    fn recycles_particles_at_the_end_of_their_life() {
+        let mut scene = Scene { gravity: 0.0, ..Scene::default() };
+        scene.emitter.lifetime = Some([1.0, 1.0]);
+        let mut system = system_with(scene, &[(vec4(0.0, 1000.0, 0.0, 0.0), vec4(0.0, 0.0, 0.0, 0.0))]);
+
+        system.update_cpu(DT);
+        assert_position(&system, 0, [0.0, 1000.0, 0.0]);
+        assert_eq!(system.particle_velocities()[0].w, 0.5);
+
+        system.update_cpu(DT);
+        assert!(system.particle_positions()[0].y >= 500.0 && system.particle_positions()[0].y <= 550.0);
+        assert_eq!(system.particle_velocities()[0].w, 0.0);
+    }
+
+    #[test]
+    fn respawns_dead_particles_at_the_emitter() {
+        let dead = (vec4(0.0, 1000.0, 0.0, 0.0), vec4(0.0, 0.0, 0.0, -1.0));
+        let alive = (vec4(0.0, 1000.0, 0.0, 0.0), vec4(0.0, 0.0, 0.0, 0.0));
+        let mut system = system_with(Scene { gravity: 0.0, ..Scene::default() }, &[dead, alive]);
+
+        system.update_cpu(DT);
+        let (position, velocity) = (system.particle_positions()[0], system.particle_velocities()[0]);
+        assert_eq!(velocity.w, 0.0);
+        assert!(position.x >= -700.0 && position.x <= 700.0, "{:?}", position);
+        assert!(position.y >= 500.0 && position.y <= 550.0, "{:?}", position);
+        assert!(position.z >= -700.0 && position.z <= 700.0, "{:?}", position);
+        assert!([velocity.x, velocity.y, velocity.z].iter().all(|v| v.abs() <= 5.0), "{:?}", velocity);
+        assert_eq!(system.particle_velocities()[1].w, 0.5);
+    }
+
+    #[test]
+    fn spawn_budget_goes_to_the_lowest_indices() {
+        let dead = (vec4(0.0, 1000.0, 0.0, 0.0), vec4(0.0, 0.0, 0.0, -1.0));
+        let mut scene = Scene { gravity: 0.0, ..Scene::default() };
+        scene.emitter.rate = Some(2.0);
+        let mut system = system_with(scene, &[dead, dead, dead]);
+
+        //One particle per step of half a second.
+        system.update_cpu(DT);
+        let ages: Vec<f32> = system.particle_velocities().iter().map(|velocity| velocity.w).collect();
+        assert_eq!(ages, vec![0.0, -1.0, -1.0]);
+
+        system.update_cpu(DT);
+        let ages: Vec<f32> = system.particle_velocities().iter().map(|velocity| velocity.w).collect();
+        assert_eq!(ages, vec![0.5, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn bursts_take_the_lowest_dead_indices() {
+        let dead = (vec4(0.0, 1000.0, 0.0, 0.0), vec4(0.0, 0.0, 0.0, -1.0));
+        let alive = (vec4(0.0, 1000.0, 0.0, 0.0), vec4(0.0, 0.0, 0.0, 0.0));
+        let mut system = system_with(Scene { gravity: 0.0, ..Scene::default() }, &[alive, dead, alive, dead, dead]);
+        let up = VelocityMode::Range { min: [0.0, 10.0, 0.0], max: [0.0, 10.0, 0.0] };
+        let down = VelocityMode::Range { min: [0.0, -10.0, 0.0], max: [0.0, -10.0, 0.0] };
+        system.emit_burst([100.0, 2000.0, 0.0], 1, up).unwrap();
+        system.emit_burst([-100.0, 3000.0, 0.0], 5, down).unwrap();
+
+        system.update_cpu(DT);
+        assert_position(&system, 1, [100.0, 2000.0, 0.0]);
+        assert_close(&system.particle_velocities()[1], [0.0, 10.0, 0.0, 0.0]);
+        for &index in &[3, 4] {
+            assert_position(&system, index, [-100.0, 3000.0, 0.0]);
+            assert_close(&system.particle_velocities()[index], [0.0, -10.0, 0.0, 0.0]);
+        }
+        assert_position(&system, 0, [0.0, 1000.0, 0.0]);
+        assert_position(&system, 2, [0.0, 1000.0, 0.0]);
+
+        //The three particles the second burst had no room for are dropped, not kept for the
+        //next step: a particle that dies now goes back to the emitter.
+        system.particle_vel[0].w = -1.0;
+        system.update_cpu(DT);
+        let position = system.particle_positions()[0];
+        assert!(position.y >= 500.0 && position.y <= 550.0, "{:?}", position);
+    }
+
+    #[test]
+    fn reflects_off_walls_with_damping() {
+        let scene = Scene {
+            gravity: 0.0,
+            bounds: Bounds {
+                max_x: BoundsFace::Reflect { damping: 0.5 },
+                min_y: BoundsFace::Reflect { damping: 1.0 },
+                ..Bounds::default()
+            },
+            ..Scene::default()
+        };
+        let mut system = system_with(scene, &[
+            (vec4(695.0, 1000.0, 0.0, 0.0), vec4(20.0, 0.0, 0.0, 0.0)),
+            (vec4(0.0, 1.0, 0.0, 0.0), vec4(0.0, -30.0, 0.0, 0.0))
+        ]);
+
+        system.update_cpu(DT);
+        assert_position(&system, 0, [699.9, 1000.0, 0.0]);
+        assert_close(&system.particle_velocities()[0], [-10.0, 0.0, 0.0, 0.5]);
+
+        assert_position(&system, 1, [0.0, 0.1, 0.0]);
+        assert_close(&system.particle_velocities()[1], [0.0, 30.0, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn reflects_off_spheres() {
+        let material = Material { restitution: 0.5, friction: 0.5, stick: 0.0, kill: false };
+        let scene = Scene {
+            gravity: 0.0,
+            spheres: Some(vec![
+                Sphere { center: [0.0, 1000.0, 0.0], radius: 100.0, material: Some(material) },
+                Sphere { center: [400.0, 1000.0, 0.0], radius: 100.0, material: None }
+            ]),
+            ..Scene::default()
+        };
+        let mut system = system_with(scene, &[
+            (vec4(-5.0, 1105.0, 0.0, 0.0), vec4(10.0, -20.0, 0.0, 0.0)),
+            (vec4(400.0, 1105.0, 0.0, 0.0), vec4(0.0, -20.0, 0.0, 0.0))
+        ]);
+
+        system.update_cpu(DT);
+        //Pushed out along the normal, which points straight up at the contact point.
+        assert_position(&system, 0, [0.0, 1100.1, 0.0]);
+        //The tangential part loses friction, the normal one is flipped and scaled by restitution.
+        assert_close(&system.particle_velocities()[0], [5.0, 10.0, 0.0, 0.5]);
+
+        //Spheres without a material reflect and then damp every fast component to a fifth.
+        assert_position(&system, 1, [400.0, 1100.1, 0.0]);
+        assert_close(&system.particle_velocities()[1], [0.0, 4.0, 0.0, 0.5]);
+    }
+
+    #[test]
+    fn stuck_particles_stay_on_the_surface() {
+        let slab = |x: f32, stick: f32| Collider {
+            shape: ColliderShape::Box { center: [x, 50.0, 0.0], half_extents: [100.0, 50.0, 100.0], rotation: [0.0; 3] },
+            restitution: 0.0,
+            friction: 1.0,
+            stick,
+            kill: false,
+            animation: None
+        };
+        let scene = Scene { gravity: 10.0, colliders: vec![slab(-200.0, 1.0), slab(200.0, 0.0)], ..Scene::default() };
+        let mut system = system_with(scene, &[
+            (vec4(-200.0, 105.0, 0.0, 0.0), vec4(0.0, -20.0, 0.0, 0.0)),
+            (vec4(200.0, 105.0, 0.0, 0.0), vec4(0.0, -20.0, 0.0, 0.0))
+        ]);
+
+        //Both land and stop, only the first one sticks.
+        system.update_cpu(DT);
+        assert_position(&system, 0, [-200.0, 100.1, 0.0]);
+        assert_close(&system.particle_velocities()[0], [0.0, 0.0, 0.0, 0.5]);
+        assert!(is_stuck(&system.particle_positions()[0]));
+        assert_position(&system, 1, [200.0, 100.1, 0.0]);
+        assert!(!is_stuck(&system.particle_positions()[1]));
+
+        //Slow particles below the emitter are recycled, stuck ones aren't and don't slide off.
+        system.update_cpu(DT);
+        assert!(system.particle_positions()[1].y >= 500.0, "{:?}", system.particle_positions()[1]);
+        for step in 2..6 {
+            assert_position(&system, 0, [-200.0, 100.1, 0.0]);
+            assert_close(&system.particle_velocities()[0], [0.0, 0.0, 0.0, step as f32 * 0.5]);
+            assert!(is_stuck(&system.particle_positions()[0]));
+            system.update_cpu(DT);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+use gl;
+use std;
+use camera::Camera;
+use graphics::framebuffer::FrameBuffer;
+use graphics::program_set::{ProgramSet, ProgramSource};
+use graphics::shader::ShaderError;
+use graphics::shader::ShaderInputData;
+use graphics::shader::ShaderType;
+use graphics::vao::VertexArrayObj;
+use graphics::vao::VertexBufferObj;
+use particle_system::{GraphicsError, ParticleSystem};
+use terrain::Terrain;
+
+//Draws any number of particle systems, and optionally a terrain, into one scene framebuffer
+//and composites it to the screen with a single bloom and tonemap pass. Needs a GL context.
+pub struct Renderer {
+    programs: ProgramSet<Program>,
+    screen_vao: VertexArrayObj,
+    fullscreen_quad_vbo: VertexBufferObj,
+    frame_buffer: FrameBuffer,
+    blur_frame_buffers: [FrameBuffer; 2],
+    terrain_vao: VertexArrayObj,
+    terrain_vbo: VertexBufferObj,
+    //0 when there is no terrain to draw, see set_terrain.
+    terrain_vertices: usize
+}
+
+//The post processing and terrain programs owned by the Renderer.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Program {
+    Screen,
+    Blur,
+    Terrain
+}
+
+//Terrain meshes are drawn with at most this many vertices along each side.
+const MAX_TERRAIN_COLUMNS: u32 = 256;
+
+
+impl Renderer {
+    //width and height are the size of the render target, see resize.
+    pub fn new(width: u32, height: u32) -> Result<Renderer, GraphicsError> {
+        let mut renderer = Renderer {
+            programs: ProgramSet::new(&[Program::Screen, Program::Blur, Program::Terrain]),
+            screen_vao: VertexArrayObj::new(),
+            fullscreen_quad_vbo: VertexBufferObj::new(),
+            frame_buffer: FrameBuffer::new(width, height),
+            blur_frame_buffers: [FrameBuffer::new(width, height), FrameBuffer::new(width, height)],
+            terrain_vao: VertexArrayObj::new(),
+            terrain_vbo: VertexBufferObj::new(),
+            terrain_vertices: 0
+        };
+
+        let quad_vertices: [f32; 24] = [ // vertex attributes for a quad that fills the entire screen in Normalized Device Coordinates.
+            // positions   // texCoords
+            -1.0,  1.0,  0.0, 1.0,
+            -1.0, -1.0,  0.0, 0.0,
+             1.0, -1.0,  1.0, 0.0,
+
+            -1.0,  1.0,  0.0, 1.0,
+             1.0, -1.0,  1.0, 0.0,
+             1.0,  1.0,  1.0, 1.0
+        ];
+        renderer.screen_vao.bind();
+        renderer.fullscreen_quad_vbo.set_buffer_data(&quad_vertices);
+        renderer.fullscreen_quad_vbo.describe_data(0, 2, 4 * std::mem::size_of::<f32>(), 0);
+        renderer.fullscreen_quad_vbo.describe_data(1, 2, 4 * std::mem::size_of::<f32>(), 2 * std::mem::size_of::<f32>());
+        renderer.screen_vao.unbind();
+
+        renderer.load_shaders().map_err(GraphicsError::Shaders)?;
+
+        Ok(renderer)
+    }
+
+    //Rebuilds the post processing programs, see ProgramSet::load_all.
+    pub fn load_shaders(&mut self) -> Result<(), Vec<ShaderError>> {
+        self.programs.load_all()
+    }
+
+    //Rebuilds the programs whose source files changed, None when nothing changed.
+    pub fn reload_changed_shaders(&mut self) -> Option<Result<(), Vec<ShaderError>>> {
+        self.programs.reload_changed()
+    }
+
+    //Draws the terrain under the particles from now on, None stops drawing it. The terrain
+    //has to be loaded already, see Terrain::load.
+    pub fn set_terrain(&mut self, terrain: Option<&Terrain>) {
+        let vertices = terrain.map_or_else(Vec::new, |terrain| terrain.mesh_vertices(MAX_TERRAIN_COLUMNS));
+        self.terrain_vertices = vertices.len() / 6;
+        if vertices.is_empty() {
+            return;
+        }
+
+        self.terrain_vao.bind();
+        self.terrain_vbo.set_buffer_data(&vertices);
+        self.terrain_vbo.describe_data(0, 3, 6 * std::mem::size_of::<f32>(), 0);
+        self.terrain_vbo.describe_data(1, 3, 6 * std::mem::size_of::<f32>(), 3 * std::mem::size_of::<f32>());
+        self.terrain_vao.unbind();
+    }
+
+    //Recreates the offscreen buffers for a new window size, render draws at this size from now on.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width == 0 || height == 0 || (width == self.frame_buffer.width() && height == self.frame_buffer.height()) {
+            return;
+        }
+
+        self.frame_buffer = FrameBuffer::new(width, height);
+        self.blur_frame_buffers = [FrameBuffer::new(width, height), FrameBuffer::new(width, height)];
+    }
+
+    //Systems share the depth buffer, so they are sorted against each other like any other geometry.
+    pub fn render(&mut self, systems: &[ParticleSystem], cam: &Camera) {
+
+        //First pass
+        let (width, height) = (self.frame_buffer.width(), self.frame_buffer.height());
+        self.frame_buffer.bind();
+        unsafe {
+            gl::Viewport(0, 0, width as i32, height as i32);
+            gl::Enable(gl::DEPTH_TEST);
+
+            let attachments: [u32; 2] = [gl::COLOR_ATTACHMENT0, gl::COLOR_ATTACHMENT1];
+            gl::DrawBuffers(2, attachments.as_ptr() as * const _);
+
+            let color: [f32; 4] = [0.003, 0.003, 0.003, 1.0];
+            let black: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+            gl::ClearBufferfv(gl::COLOR, 0, color.as_ptr() as *const _);
+            gl::ClearBufferfv(gl::COLOR, 1, black.as_ptr() as *const _);
+            //gl::ClearColor(0.003, 0.003, 0.003, 1.0);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+        }
+
+        if self.terrain_vertices > 0 {
+            let terrain_program = self.programs.get(Program::Terrain);
+            terrain_program.bind();
+            terrain_program.set_uniform_matrix4("view_from_world", cam.view_from_world.as_ref());
+            terrain_program.set_uniform_matrix4("proj_from_view", cam.proj_from_view.as_ref());
+            self.terrain_vao.bind();
+            unsafe {
+                gl::DrawArrays(gl::TRIANGLES, 0, self.terrain_vertices as i32);
+            }
+            self.terrain_vao.unbind();
+            terrain_program.unbind();
+        }
+
+        for system in systems {
+            system.render_particles(cam);
+        }
+        self.frame_buffer.unbind();
+
+        {
+            let blur_shader = self.programs.get(Program::Blur);
+            blur_shader.bind();
+            self.screen_vao.bind();
+            unsafe {
+                gl::Disable(gl::DEPTH_TEST);
+            }
+
+            //Do the blur
+            let blur_passes = 5;
+            let mut vertical = false;
+            let mut first_pass = true;
+            for _ in 0..blur_passes {
+                let (source, dest) = match vertical {
+                    false => (0, 1),
+                    true => (1, 0)
+                };
+
+                self.blur_frame_buffers[dest].bind();
+                let mut color_buffer = self.blur_frame_buffers[source].get_color_texture();
+                if first_pass {
+                    color_buffer = self.frame_buffer.get_highlights_texture();
+                    first_pass = false;
+                }
+                color_buffer.bind();
+                blur_shader.set_uniform_1i("vertical", vertical as i32);
+                unsafe {
+                    gl::DrawArrays(gl::TRIANGLES, 0, 6);
+                }
+
+                color_buffer.unbind();
+
+                vertical = !vertical;
+            }
+
+            self.screen_vao.unbind();
+            blur_shader.unbind();
+        }
+
+        //Final pass
+        unsafe{
+            gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+            gl::ClearColor(1.0, 1.0, 1.0, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+        }
+
+        let screen_program = self.programs.get(Program::Screen);
+        screen_program.bind();
+        unsafe {
+            self.screen_vao.bind();
+            gl::Disable(gl::DEPTH_TEST);
+            screen_program.set_uniform_1i("screenTexture", 0);
+            screen_program.set_uniform_1i("bloom", 1);
+
+            let color_buffer = self.frame_buffer.get_color_texture();
+            gl::ActiveTexture(gl::TEXTURE0);
+            color_buffer.bind();
+
+            let blured_texture = self.blur_frame_buffers[1].get_color_texture();
+            gl::ActiveTexture(gl::TEXTURE1);
+            blured_texture.bind();
+
+            gl::DrawArrays(gl::TRIANGLES, 0, 6);
+
+            color_buffer.unbind();
+            self.screen_vao.unbind();
+        }
+        screen_program.unbind();
+    }
+}
+
+
+impl ProgramSource for Program {
+    fn inputs(&self) -> Vec<ShaderInputData> {
+        match *self {
+            Program::Screen => vec![ShaderInputData::new(ShaderType::Vertex, "shaders/fullscreen_quad.v.glsl"),
+                ShaderInputData::new(ShaderType::Fragment, "shaders/copy_texture_to_quad.p.glsl")],
+            Program::Blur => vec![ShaderInputData::new(ShaderType::Vertex, "shaders/fullscreen_quad.v.glsl"),
+                ShaderInputData::new(ShaderType::Fragment, "shaders/blur_shader.p.glsl")],
+            Program::Terrain => vec![ShaderInputData::new(ShaderType::Vertex, "shaders/terrain.v.glsl"),
+                ShaderInputData::new(ShaderType::Fragment, "shaders/terrain.p.glsl")]
+        }
+    }
+}
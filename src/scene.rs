@@ -0,0 +1,326 @@
+use std;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use toml;
+use emitter::Emitter;
+use collider::{Collider, Material};
+use terrain::Terrain;
+pub use bounds::{Bounds, BoundsFace};
+
+//Maximum number of keys in each of the Appearance curves.
+pub const MAX_CURVE_POINTS: usize = 8;
+
+//Describes everything needed to build the ParticleSystems of a scene. The top level
+//particle_count, gravity, initial, emitter and appearance describe the first system,
+//systems adds more. Missing fields fall back to the values the demo has always used,
+//so an empty file is a valid scene.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scene {
+    pub particle_count: usize,
+    //Drives all the randomness, None picks a different seed every run.
+    pub seed: Option<u64>,
+    pub gravity: f32,
+    pub initial: Volume,
+    pub emitter: Emitter,
+    pub bounds: Bounds,
+    pub appearance: Appearance,
+    //None means "generate random spheres", an empty list means no spheres at all.
+    pub spheres: Option<Vec<Sphere>>,
+    //Boxes, planes, capsules, cylinders, tori and meshes, see Collider.
+    pub colliders: Vec<Collider>,
+    //Heightmap ground particles bounce off, above the floor of bounds.
+    pub terrain: Option<Terrain>,
+    //Additional systems drawn into the same frame, see System.
+    pub systems: Vec<System>
+}
+
+//A particle system of the scene. It shares the seed, bounds, spheres, colliders and terrain with the rest of
+//the scene; missing fields take the same defaults as in Scene.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct System {
+    pub particle_count: usize,
+    pub gravity: f32,
+    pub initial: Volume,
+    pub emitter: Emitter,
+    pub appearance: Appearance
+}
+
+//Axis aligned box, used for the initial particle placement.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Volume {
+    pub min: [f32; 3],
+    pub max: [f32; 3]
+}
+
+//How particles look over their life. Keys are spread evenly from birth (first) to death
+//(last) and linearly interpolated; a single key is a constant. Particles without a lifetime
+//always use the first key.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Appearance {
+    //RGBA multiplied with the speed based color.
+    pub color_over_life: Vec<[f32; 4]>,
+    //Multiplier for the particle size.
+    pub size_over_life: Vec<f32>
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sphere {
+    pub center: [f32; 3],
+    pub radius: f32,
+    //None keeps the classic response, see ParticleSystem::set_sphere_material.
+    #[serde(default)]
+    pub material: Option<Material>
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Invalid(String)
+}
+
+
+impl Scene {
+    //Files the scene refers to, like an emitter mesh, are relative to the scene file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+        let path = path.as_ref();
+        let mut file = File::open(path).map_err(SceneError::Io)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents).map_err(SceneError::Io)?;
+
+        Scene::parse(&contents, path.parent().unwrap_or_else(|| Path::new("")))
+    }
+
+    //Files the scene refers to are relative to the working directory.
+    pub fn from_toml(contents: &str) -> Result<Scene, SceneError> {
+        Scene::parse(contents, Path::new(""))
+    }
+
+    fn parse(contents: &str, base_dir: &Path) -> Result<Scene, SceneError> {
+        let mut scene: Scene = toml::from_str(contents).map_err(SceneError::Parse)?;
+        scene.validate()?;
+        scene.emitter.load_mesh(base_dir).map_err(SceneError::Invalid)?;
+        for system in &mut scene.systems {
+            system.emitter.load_mesh(base_dir).map_err(SceneError::Invalid)?;
+        }
+        for collider in &mut scene.colliders {
+            collider.load_mesh(base_dir).map_err(SceneError::Invalid)?;
+        }
+        if let Some(ref mut terrain) = scene.terrain {
+            terrain.load(base_dir).map_err(SceneError::Invalid)?;
+        }
+
+        Ok(scene)
+    }
+
+    pub fn validate(&self) -> Result<(), SceneError> {
+        //Particles need room between the walls, unlike the initial volume that may be flat.
+        if self.bounds.min.iter().zip(self.bounds.max.iter()).any(|(a, b)| a >= b) {
+            return Err(SceneError::Invalid(format!("bounds min {:?} has to be below max {:?}", self.bounds.min, self.bounds.max)));
+        }
+        self.bounds.validate().map_err(SceneError::Invalid)?;
+        for system in self.systems() {
+            system.validate()?;
+        }
+
+        if let Some(ref spheres) = self.spheres {
+            if let Some(sphere) = spheres.iter().find(|s| s.radius <= 0.0) {
+                return Err(SceneError::Invalid(format!("sphere at {:?} has a non positive radius", sphere.center)));
+            }
+            for sphere in spheres {
+                if let Some(ref material) = sphere.material {
+                    material.validate().map_err(|err| SceneError::Invalid(format!("sphere at {:?}: {}", sphere.center, err)))?;
+                }
+            }
+        }
+
+        for collider in &self.colliders {
+            collider.validate().map_err(SceneError::Invalid)?;
+        }
+        if let Some(ref terrain) = self.terrain {
+            terrain.validate().map_err(SceneError::Invalid)?;
+        }
+
+        Ok(())
+    }
+
+    //Every system of the scene, the one described by the top level fields first.
+    pub fn systems(&self) -> Vec<System> {
+        let mut systems = vec![System {
+            particle_count: self.particle_count,
+            gravity: self.gravity,
+            initial: self.initial,
+            emitter: self.emitter.clone(),
+            appearance: self.appearance.clone()
+        }];
+        systems.extend(self.systems.iter().cloned());
+
+        systems
+    }
+}
+
+
+impl System {
+    pub fn validate(&self) -> Result<(), SceneError> {
+        check_box("initial", &self.initial.min, &self.initial.max)?;
+        self.emitter.validate().map_err(SceneError::Invalid)?;
+        check_curve("color_over_life", self.appearance.color_over_life.len())?;
+        check_curve("size_over_life", self.appearance.size_over_life.len())?;
+
+        Ok(())
+    }
+}
+
+
+impl Default for Scene {
+    fn default() -> Scene {
+        Scene {
+            particle_count: 1024 * 1024 * 8,
+            seed: None,
+            gravity: 9.8,
+            initial: Volume {
+                min: [-1000.0, -1000.0, -1000.0],
+                max: [1000.0, 1000.0, 1000.0]
+            },
+            emitter: Emitter::default(),
+            bounds: Bounds::default(),
+            appearance: Appearance::default(),
+            spheres: None,
+            colliders: Vec::new(),
+            terrain: None,
+            systems: Vec::new()
+        }
+    }
+}
+
+
+impl Default for System {
+    fn default() -> System {
+        Scene::default().systems().remove(0)
+    }
+}
+
+
+impl Default for Appearance {
+    fn default() -> Appearance {
+        Appearance {
+            color_over_life: vec![[1.0, 1.0, 1.0, 1.0]],
+            size_over_life: vec![1.0]
+        }
+    }
+}
+
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SceneError::Io(ref err) => write!(f, "can't read scene file: {}", err),
+            SceneError::Parse(ref err) => write!(f, "can't parse scene file: {}", err),
+            SceneError::Invalid(ref msg) => write!(f, "invalid scene: {}", msg)
+        }
+    }
+}
+
+
+impl std::error::Error for SceneError {}
+
+
+fn check_curve(name: &str, points: usize) -> Result<(), SceneError> {
+    if points == 0 || points > MAX_CURVE_POINTS {
+        return Err(SceneError::Invalid(format!(
+            "{} has {} keys, between 1 and {} are supported", name, points, MAX_CURVE_POINTS)));
+    }
+
+    Ok(())
+}
+
+
+fn check_box(name: &str, min: &[f32; 3], max: &[f32; 3]) -> Result<(), SceneError> {
+    if min.iter().zip(max.iter()).any(|(a, b)| a > b) {
+        return Err(SceneError::Invalid(format!("{} min {:?} is above max {:?}", name, min, max)));
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(contents: &str) -> String {
+        match Scene::from_toml(contents) {
+            Err(SceneError::Invalid(message)) => message,
+            other => panic!("expected an invalid scene, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn parses_the_example_scene() {
+        let scene = Scene::load("scenes/example.toml").unwrap();
+
+        assert_eq!(scene.particle_count, 1048576);
+        assert_eq!(scene.emitter.rate, Some(100000.0));
+        assert_eq!(scene.bounds.min_y, BoundsFace::Kill);
+        assert_eq!(scene.spheres.as_ref().map(Vec::len), Some(3));
+        assert_eq!(scene.colliders.len(), 2);
+        assert_eq!(scene.systems().len(), 2);
+        assert_eq!(scene.systems()[1].particle_count, 131072);
+    }
+
+    #[test]
+    fn empty_scene_uses_the_defaults() {
+        let scene = Scene::from_toml("").unwrap();
+
+        assert_eq!(scene.particle_count, Scene::default().particle_count);
+        assert!(scene.spheres.is_none());
+        assert_eq!(scene.systems().len(), 1);
+    }
+
+    #[test]
+    fn rejects_a_negative_rate() {
+        let message = invalid("[emitter]\nrate = -10.0\n");
+        assert!(message.contains("rate"), "{}", message);
+
+        let message = invalid("[[systems]]\nemitter = { rate = -10.0 }\n");
+        assert!(message.contains("rate"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_zero_size_and_inverted_boxes() {
+        let message = invalid("[bounds]\nmin = [0.0, 0.0, 0.0]\nmax = [100.0, 0.0, 100.0]\n");
+        assert!(message.contains("bounds"), "{}", message);
+
+        let message = invalid("[emitter]\nshape = { type = \"box\", min = [0.0, 10.0, 0.0], max = [10.0, 0.0, 10.0] }\n");
+        assert!(message.contains("box"), "{}", message);
+
+        let message = invalid("[initial]\nmin = [0.0, 0.0, 0.0]\nmax = [-1.0, 0.0, 0.0]\n");
+        assert!(message.contains("initial"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_non_positive_sphere_radii() {
+        let message = invalid("[[spheres]]\ncenter = [0.0, 0.0, 0.0]\nradius = 0.0\n");
+        assert!(message.contains("radius"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_an_unknown_emitter_shape() {
+        match Scene::from_toml("[emitter]\nshape = { type = \"pyramid\", position = [0.0, 0.0, 0.0] }\n") {
+            Err(SceneError::Parse(err)) => assert!(err.to_string().contains("pyramid"), "{}", err),
+            other => panic!("expected a parse error, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        assert!(matches!(Scene::from_toml("particle_cuont = 10\n"), Err(SceneError::Parse(_))));
+    }
+}
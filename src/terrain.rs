@@ -0,0 +1,348 @@
+use graphics::shader::ShaderProgram;
+use png;
+use std;
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+
+//Ground shaped by a grayscale heightmap, particles bounce off it. Mirrored by
+//shaders/terrain.glsl, keep the two in sync.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Terrain {
+    //PNG (grayscale or the first channel of a color image) or RAW (16 bit little endian
+    //values, row after row), relative to the scene file.
+    pub path: String,
+    //World x and z of the first and the last pixel. Image columns run along x, rows along z.
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+    //y of black pixels.
+    #[serde(default)]
+    pub base: f32,
+    //How much higher white pixels are than black ones.
+    pub height: f32,
+    //Columns and rows of a RAW file, a square is assumed when missing.
+    #[serde(default)]
+    pub raw_size: Option<[u32; 2]>,
+    //Same as for a Collider.
+    #[serde(default = "default_restitution")]
+    pub restitution: f32,
+    #[serde(default = "default_friction")]
+    pub friction: f32,
+    //Draw the terrain, see Renderer::set_terrain.
+    #[serde(default)]
+    pub mesh: bool,
+    //Columns and rows read by load.
+    #[serde(skip)]
+    size: [u32; 2],
+    //Heights in [0, 1], size[0] per row.
+    #[serde(skip)]
+    heights: Arc<Vec<f32>>
+}
+
+//How far particles are pushed above the surface, same as for colliders.
+const SURFACE_OFFSET: f32 = 0.1;
+
+//Height of a terrain whose heightmap wasn't loaded, nothing is ever below it.
+const UNLOADED_HEIGHT: f32 = -1.0e9;
+
+
+impl Terrain {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.min[0] >= self.max[0] || self.min[1] >= self.max[1] {
+            return Err(format!("terrain min {:?} has to be below max {:?}", self.min, self.max));
+        }
+        if self.height <= 0.0 {
+            return Err(format!("terrain height has to be positive, got {}", self.height));
+        }
+        if let Some(size) = self.raw_size {
+            if size[0] < 2 || size[1] < 2 {
+                return Err(format!("terrain raw_size {:?} has to be at least 2 x 2", size));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.restitution) || !(0.0..=1.0).contains(&self.friction) {
+            return Err(format!("terrain restitution {} and friction {} have to be between 0 and 1",
+                self.restitution, self.friction));
+        }
+
+        Ok(())
+    }
+
+    //Reads the heightmap, path is relative to base_dir. Files ending in .raw or .r16 are RAW,
+    //everything else is read as PNG.
+    pub fn load(&mut self, base_dir: &Path) -> Result<(), String> {
+        let full_path = base_dir.join(&self.path);
+        let extension = full_path.extension().and_then(|extension| extension.to_str()).unwrap_or("").to_lowercase();
+        let (size, heights) = match extension.as_str() {
+            "raw" | "r16" => load_raw(&full_path, self.raw_size),
+            _ => load_png(&full_path)
+        }.map_err(|err| format!("can't load terrain {}: {}", full_path.display(), err))?;
+
+        if size[0] < 2 || size[1] < 2 {
+            return Err(format!("terrain {} has to be at least 2 x 2 pixels", full_path.display()));
+        }
+
+        self.size = size;
+        self.heights = Arc::new(heights);
+
+        Ok(())
+    }
+
+    //Columns and rows of the heightmap, [0, 0] until load is called.
+    pub fn size(&self) -> [u32; 2] {
+        self.size
+    }
+
+    //Heights in [0, 1], size()[0] per row.
+    pub fn heights(&self) -> &[f32] {
+        &self.heights
+    }
+
+    pub fn set_uniforms(&self, program: &ShaderProgram) {
+        program.set_uniform_2i("terrainSize", &[self.size[0] as i32, self.size[1] as i32]);
+        program.set_uniform_2f("terrainMin", &self.min);
+        program.set_uniform_2f("terrainMax", &self.max);
+        program.set_uniform_1f("terrainBase", self.base);
+        program.set_uniform_1f("terrainHeight", self.height);
+        program.set_uniform_1f("terrainRestitution", self.restitution);
+        program.set_uniform_1f("terrainFriction", self.friction);
+    }
+
+    //Same as terrainHeightAt() in shaders/terrain.glsl: bilinear between the pixels, the border
+    //pixels continue outside the terrain. UNLOADED_HEIGHT until load is called.
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        if !self.is_loaded() {
+            return UNLOADED_HEIGHT;
+        }
+
+        let point = [x, z];
+        let mut index = [0usize; 2];
+        let mut fraction = [0.0f32; 2];
+        for axis in 0..2 {
+            let t = ((point[axis] - self.min[axis]) / (self.max[axis] - self.min[axis])).clamp(0.0, 1.0);
+            let g = t * (self.size[axis] - 1) as f32;
+            let i = (g.floor() as usize).min(self.size[axis] as usize - 2);
+            index[axis] = i;
+            fraction[axis] = g - i as f32;
+        }
+
+        let columns = self.size[0] as usize;
+        let sample = |dx: usize, dz: usize| self.heights[index[0] + dx + (index[1] + dz) * columns];
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let h = lerp(lerp(sample(0, 0), sample(1, 0), fraction[0]),
+                     lerp(sample(0, 1), sample(1, 1), fraction[0]), fraction[1]);
+
+        self.base + h * self.height
+    }
+
+    //Same as terrainNormal() in shaders/terrain.glsl, from the height gradient over one pixel.
+    pub fn normal_at(&self, x: f32, z: f32) -> [f32; 3] {
+        if !self.is_loaded() {
+            return [0.0, 1.0, 0.0];
+        }
+
+        let texel = [(self.max[0] - self.min[0]) / (self.size[0] - 1) as f32,
+                     (self.max[1] - self.min[1]) / (self.size[1] - 1) as f32];
+        let dx = self.height_at(x + texel[0], z) - self.height_at(x - texel[0], z);
+        let dz = self.height_at(x, z + texel[1]) - self.height_at(x, z - texel[1]);
+
+        let n = [-dx / (2.0 * texel[0]), 1.0, -dz / (2.0 * texel[1])];
+        let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+        [n[0] / length, n[1] / length, n[2] / length]
+    }
+
+    //Same as collideWithTerrain() in shaders/terrain.glsl: lifts a particle that is below the
+    //surface onto it and bounces its velocity off the slope. Like the shader, which skips the
+    //terrain while terrainSize is 0, a terrain that wasn't loaded never collides.
+    pub fn collide(&self, position: &mut [f32; 3], velocity: &mut [f32; 3]) {
+        if !self.is_loaded() {
+            return;
+        }
+
+        let ground = self.height_at(position[0], position[2]);
+        if position[1] >= ground {
+            return;
+        }
+
+        let n = self.normal_at(position[0], position[2]);
+        position[1] = ground + SURFACE_OFFSET;
+
+        let vn = velocity[0] * n[0] + velocity[1] * n[1] + velocity[2] * n[2];
+        if vn < 0.0 {
+            for axis in 0..3 {
+                let tangent = velocity[axis] - n[axis] * vn;
+                velocity[axis] = tangent * (1.0 - self.friction) - n[axis] * vn * self.restitution;
+            }
+        }
+    }
+
+    //Triangles for drawing, position and normal (6 floats) per vertex. Large heightmaps are
+    //drawn with every step-th pixel so the mesh stays below max_columns along each side.
+    pub fn mesh_vertices(&self, max_columns: u32) -> Vec<f32> {
+        if !self.is_loaded() {
+            return Vec::new();
+        }
+
+        let pixels = |size: u32| {
+            let step = ((size - 1) as f32 / (max_columns.max(2) - 1) as f32).ceil().max(1.0) as usize;
+            let mut pixels: Vec<u32> = (0..size).step_by(step).collect();
+            if pixels.last() != Some(&(size - 1)) {
+                pixels.push(size - 1);
+            }
+            pixels
+        };
+        let (columns, rows) = (pixels(self.size[0]), pixels(self.size[1]));
+
+        let vertex = |column: u32, row: u32| {
+            let x = self.min[0] + (self.max[0] - self.min[0]) * column as f32 / (self.size[0] - 1) as f32;
+            let z = self.min[1] + (self.max[1] - self.min[1]) * row as f32 / (self.size[1] - 1) as f32;
+            let n = self.normal_at(x, z);
+            [x, self.height_at(x, z), z, n[0], n[1], n[2]]
+        };
+
+        let mut vertices = Vec::new();
+        for z in rows.windows(2) {
+            for x in columns.windows(2) {
+                let (a, b, c, d) = (vertex(x[0], z[0]), vertex(x[1], z[0]), vertex(x[0], z[1]), vertex(x[1], z[1]));
+                for corner in [a, c, b, b, c, d].iter() {
+                    vertices.extend_from_slice(corner);
+                }
+            }
+        }
+
+        vertices
+    }
+
+    fn is_loaded(&self) -> bool {
+        self.size[0] >= 2 && self.size[1] >= 2
+    }
+}
+
+
+fn default_restitution() -> f32 {
+    0.2
+}
+
+
+fn default_friction() -> f32 {
+    0.8
+}
+
+
+fn load_png(path: &Path) -> Result<([u32; 2], Vec<f32>), String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).map_err(|err| err.to_string())?;
+
+    let (color_type, bit_depth) = reader.output_color_type();
+    let bytes = if bit_depth == png::BitDepth::Sixteen { 2 } else { 1 };
+    let pixel_size = color_type.samples() * bytes;
+
+    let mut heights = Vec::with_capacity((info.width * info.height) as usize);
+    for row in buffer.chunks(info.line_size).take(info.height as usize) {
+        for pixel in row.chunks(pixel_size).take(info.width as usize) {
+            heights.push(match bytes {
+                2 => u16::from_be_bytes([pixel[0], pixel[1]]) as f32 / 65535.0,
+                _ => pixel[0] as f32 / 255.0
+            });
+        }
+    }
+
+    Ok(([info.width, info.height], heights))
+}
+
+
+fn load_raw(path: &Path, size: Option<[u32; 2]>) -> Result<([u32; 2], Vec<f32>), String> {
+    let bytes = std::fs::read(path).map_err(|err| err.to_string())?;
+    let count = bytes.len() / 2;
+    let size = size.unwrap_or_else(|| {
+        let side = (count as f64).sqrt().round() as u32;
+        [side, side]
+    });
+
+    if bytes.len() % 2 != 0 || (size[0] * size[1]) as usize != count {
+        return Err(format!("{} bytes don't hold {} x {} 16 bit heights", bytes.len(), size[0], size[1]));
+    }
+
+    let heights = bytes.chunks(2)
+        .map(|value| u16::from_le_bytes([value[0], value[1]]) as f32 / 65535.0)
+        .collect();
+
+    Ok((size, heights))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //size[0] x size[1] heightmap from (0, 0) to max, with black at y = 0 and white at y = 10.
+    fn terrain(max: [f32; 2], size: [u32; 2], heights: Vec<f32>) -> Terrain {
+        Terrain {
+            path: String::new(),
+            min: [0.0, 0.0],
+            max,
+            base: 0.0,
+            height: 10.0,
+            raw_size: None,
+            restitution: default_restitution(),
+            friction: default_friction(),
+            mesh: false,
+            size,
+            heights: Arc::new(heights)
+        }
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn height_is_bilinear_between_the_pixels() {
+        let mut terrain = terrain([20.0, 10.0], [3, 2], vec![
+            0.0, 0.5, 1.0,
+            0.2, 0.2, 0.2
+        ]);
+        terrain.base = 5.0;
+
+        assert_close(terrain.height_at(0.0, 0.0), 5.0);
+        assert_close(terrain.height_at(20.0, 0.0), 15.0);
+        assert_close(terrain.height_at(15.0, 0.0), 12.5);
+        assert_close(terrain.height_at(5.0, 5.0), 7.25);
+        assert_close(terrain.height_at(15.0, 2.5), 11.125);
+        //The border pixels continue outside.
+        assert_close(terrain.height_at(-100.0, 0.0), 5.0);
+        assert_close(terrain.height_at(100.0, 100.0), 7.0);
+    }
+
+    #[test]
+    fn normal_follows_the_slope() {
+        //Rises by 0.25 per unit along x and along z.
+        let terrain = terrain([20.0, 20.0], [3, 3], vec![
+            0.0, 0.25, 0.5,
+            0.25, 0.5, 0.75,
+            0.5, 0.75, 1.0
+        ]);
+
+        let n = terrain.normal_at(10.0, 10.0);
+        let length = (0.25f32 * 0.25 * 2.0 + 1.0).sqrt();
+        assert_close(n[0], -0.25 / length);
+        assert_close(n[1], 1.0 / length);
+        assert_close(n[2], -0.25 / length);
+    }
+
+    #[test]
+    fn unloaded_terrain_never_collides() {
+        let terrain = terrain([20.0, 20.0], [0, 0], Vec::new());
+        let (mut position, mut velocity) = ([5.0, -100.0, 5.0], [0.0, -10.0, 0.0]);
+
+        terrain.collide(&mut position, &mut velocity);
+        assert_eq!(position, [5.0, -100.0, 5.0]);
+        assert_eq!(velocity, [0.0, -10.0, 0.0]);
+        assert_eq!(terrain.normal_at(5.0, 5.0), [0.0, 1.0, 0.0]);
+        assert!(terrain.mesh_vertices(64).is_empty());
+    }
+}
@@ -0,0 +1,233 @@
+//Steps a small fixed-seed scene on the GPU, draws it with Renderer::render and compares
+//the composited frames against the reference images in tests/golden.
+//
+//Needs an OpenGL 4.3 context; SDL's offscreen driver is used so Mesa llvmpipe is enough.
+//Without SDL video or a 4.3 context the test prints why and passes without comparing.
+//Run with UPDATE_GOLDEN=1 to regenerate the references after an intentional change,
+//mismatching frames are written to target/golden-diff together with a diff image.
+
+extern crate cgmath;
+extern crate gl;
+extern crate png;
+extern crate rust_particles;
+extern crate sdl2;
+
+use rust_particles::graphics::capture;
+use rust_particles::scene::Sphere;
+use rust_particles::{Camera, ParticleSystem, Renderer, Scene};
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+const WIDTH: u32 = 1600;
+const HEIGHT: u32 = 900;
+
+//Images are compared on the mean absolute luminance difference of BLOCK_SIZE x BLOCK_SIZE
+//blocks. Rounding noise stays below BLOCK_TOLERANCE, and MAX_DIFFERENT_BLOCKS leaves room
+//for a few particles that land elsewhere, but a changed color, blur, exposure or collision
+//response does not fit in either.
+const BLOCK_SIZE: usize = 4;
+const BLOCK_TOLERANCE: f32 = 6.0;
+const MAX_DIFFERENT_BLOCKS: f32 = 0.005;
+
+struct Image {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>
+}
+
+
+//Gravity is high enough for the emitter slab to reach the spheres within 90 frames.
+fn golden_scene() -> Scene {
+    Scene {
+        particle_count: 64 * 1024,
+        seed: Some(1234),
+        gravity: 400.0,
+        spheres: Some(vec![
+            Sphere { center: [0.0, 0.0, 0.0], radius: 300.0, material: None },
+            Sphere { center: [-450.0, 0.0, 350.0], radius: 150.0, material: None },
+            Sphere { center: [400.0, 0.0, -300.0], radius: 200.0, material: None },
+        ]),
+        ..Scene::default()
+    }
+}
+
+
+//Hidden window with a current OpenGL 4.3 core context. The window and the context have to
+//outlive the test.
+fn create_context() -> Result<(sdl2::video::Window, sdl2::video::GLContext), String> {
+    if std::env::var_os("SDL_VIDEODRIVER").is_none() {
+        std::env::set_var("SDL_VIDEODRIVER", "offscreen");
+    }
+
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+    let gl_attr = video_subsystem.gl_attr();
+    gl_attr.set_context_profile(sdl2::video::GLProfile::Core);
+    gl_attr.set_context_version(4, 3);
+
+    let window = video_subsystem
+        .window("golden", WIDTH, HEIGHT)
+        .opengl()
+        .hidden()
+        .build()
+        .map_err(|err| err.to_string())?;
+    let gl_context = window.gl_create_context()?;
+    gl::load_with(|name| video_subsystem.gl_get_proc_address(name) as *const _);
+
+    let (mut major, mut minor) = (0, 0);
+    unsafe {
+        gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+        gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+    }
+    if (major, minor) < (4, 3) {
+        return Err(format!("got OpenGL {}.{}", major, minor));
+    }
+
+    Ok((window, gl_context))
+}
+
+
+#[test]
+fn render_matches_golden_images() {
+    let _context = match create_context() {
+        Ok(context) => context,
+        Err(err) => {
+            println!("Skipping the golden image comparison, no OpenGL 4.3 context: {}", err);
+            return;
+        }
+    };
+    unsafe {
+        gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
+        gl::Enable(gl::BLEND);
+    }
+
+    let scene = golden_scene();
+    let mut cam = Camera::new();
+    //Above the default camera, so the splashes on the far side of the spheres are visible too.
+    cam.position = cgmath::Point3::new(0.0, 900.0, -1500.0);
+    cam.set_viewport_size(WIDTH, HEIGHT);
+    let mut particle_system = ParticleSystem::from_scene(&scene);
+    if let Err(err) = particle_system.init_graphics_resources() {
+        panic!("{}", err);
+    }
+    let mut renderer = Renderer::new(WIDTH, HEIGHT).unwrap_or_else(|err| panic!("{}", err));
+
+    //Frame 1 shows the emitter slab the initial particles were recycled into, by frame 90 the
+    //slab has fallen onto the spheres and splashes off all three of them.
+    let checkpoints = [(1, "spheres_frame_001"), (90, "spheres_frame_090")];
+    let mut failures = Vec::new();
+    let mut frame = 0;
+    for &(checkpoint, name) in checkpoints.iter() {
+        while frame < checkpoint {
+            particle_system.update(1.0 / 60.0).unwrap_or_else(|err| panic!("{}", err));
+            renderer.render(std::slice::from_ref(&particle_system), &cam);
+            frame += 1;
+        }
+
+        let actual = Image {
+            width: WIDTH,
+            height: HEIGHT,
+            pixels: capture::read_pixels(WIDTH, HEIGHT)
+        };
+        if let Err(msg) = check_golden(name, &actual) {
+            failures.push(msg);
+        }
+    }
+
+    assert!(failures.is_empty(), "Golden image mismatch:\n{}", failures.join("\n"));
+}
+
+
+fn check_golden(name: &str, actual: &Image) -> Result<(), String> {
+    let golden_path = Path::new("tests/golden").join(format!("{}.png", name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all("tests/golden").unwrap();
+        capture::save_png(&golden_path, actual.width, actual.height, &actual.pixels).unwrap();
+        println!("Updated {}", golden_path.display());
+        return Ok(());
+    }
+
+    let expected = load_png(&golden_path).map_err(|err| {
+        format!("{}: can't load reference ({}), run with UPDATE_GOLDEN=1 to create it", name, err)
+    })?;
+    if expected.width != actual.width || expected.height != actual.height {
+        return Err(format!("{}: reference is {}x{}, rendered {}x{}",
+            name, expected.width, expected.height, actual.width, actual.height));
+    }
+
+    let different = different_block_ratio(&expected, actual);
+    if different <= MAX_DIFFERENT_BLOCKS {
+        return Ok(());
+    }
+
+    let out_dir = PathBuf::from("target/golden-diff");
+    std::fs::create_dir_all(&out_dir).unwrap();
+    let actual_path = out_dir.join(format!("{}.actual.png", name));
+    let diff_path = out_dir.join(format!("{}.diff.png", name));
+    capture::save_png(&actual_path, actual.width, actual.height, &actual.pixels).unwrap();
+    capture::save_png(&diff_path, actual.width, actual.height, &diff_pixels(&expected, actual)).unwrap();
+
+    Err(format!("{}: {:.2}% of the image differs (max {:.2}%), see {} and {}",
+        name, different * 100.0, MAX_DIFFERENT_BLOCKS * 100.0, actual_path.display(), diff_path.display()))
+}
+
+
+fn load_png(path: &Path) -> Result<Image, String> {
+    let file = File::open(path).map_err(|err| err.to_string())?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(|err| err.to_string())?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).map_err(|err| err.to_string())?;
+    if info.color_type != png::ColorType::Rgba || info.bit_depth != png::BitDepth::Eight {
+        return Err(format!("expected 8 bit RGBA, got {:?} {:?}", info.color_type, info.bit_depth));
+    }
+    pixels.truncate(info.buffer_size());
+
+    Ok(Image { width: info.width, height: info.height, pixels })
+}
+
+
+fn luminance(pixels: &[u8], index: usize) -> f32 {
+    let p = &pixels[index * 4..index * 4 + 3];
+    0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32
+}
+
+
+fn different_block_ratio(expected: &Image, actual: &Image) -> f32 {
+    let width = expected.width as usize;
+    let height = expected.height as usize;
+    let blocks_x = width / BLOCK_SIZE;
+    let blocks_y = height / BLOCK_SIZE;
+
+    let mut different = 0;
+    for by in 0..blocks_y {
+        for bx in 0..blocks_x {
+            let mut delta = 0.0;
+            for y in by * BLOCK_SIZE..(by + 1) * BLOCK_SIZE {
+                for x in bx * BLOCK_SIZE..(bx + 1) * BLOCK_SIZE {
+                    let index = y * width + x;
+                    delta += (luminance(&actual.pixels, index) - luminance(&expected.pixels, index)).abs();
+                }
+            }
+
+            if delta / (BLOCK_SIZE * BLOCK_SIZE) as f32 > BLOCK_TOLERANCE {
+                different += 1;
+            }
+        }
+    }
+
+    different as f32 / (blocks_x * blocks_y) as f32
+}
+
+
+//Absolute per channel difference, amplified so small changes are visible.
+fn diff_pixels(expected: &Image, actual: &Image) -> Vec<u8> {
+    expected.pixels.chunks(4).zip(actual.pixels.chunks(4))
+        .flat_map(|(e, a)| {
+            let d = |i: usize| (e[i] as i32 - a[i] as i32).unsigned_abs().saturating_mul(8).min(255) as u8;
+            vec![d(0), d(1), d(2), 255]
+        })
+        .collect()
+}